@@ -0,0 +1,626 @@
+//! Just enough of TOML and JSON to read configuration files, and JSON
+//! request and response bodies.
+//!
+//! Both formats are parsed into the same flat list of dotted keys, so a
+//! `[timeouts]` table holding `header = 10` in TOML and
+//! `{"timeouts": {"header": 10}}` in JSON both come out as `timeouts.header`.
+//! JSON numbers that are not integers are kept as written, since nothing
+//! here does arithmetic on them. TOML floats and dates, multi-line strings
+//! and inline tables are not supported, as no setting needs them.
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while, take_while1},
+    character::complete::{char, digit1, line_ending, multispace0, not_line_ending},
+    combinator::{cut, eof, map, map_res, opt, recognize, value},
+    error::{context, VerboseError, VerboseErrorKind},
+    multi::{many0, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+    IResult,
+};
+
+type ParseResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    /// A JSON number that is not an integer, or too large for one, as
+    /// written.
+    Number(String),
+    Bool(bool),
+    Array(Vec<Value>),
+    Table(Vec<(String, Value)>),
+    Null,
+}
+
+impl Value {
+    /// The value as JSON text.
+    pub fn to_json(self: &Self) -> String {
+        let mut json = String::new();
+        self.write_json(&mut json);
+
+        json
+    }
+
+    fn write_json(self: &Self, json: &mut String) {
+        match self {
+            Value::String(string) => write_json_string(string, json),
+            Value::Integer(integer) => json.push_str(&integer.to_string()),
+            Value::Number(number) => json.push_str(number),
+            Value::Bool(bool) => json.push_str(&bool.to_string()),
+            Value::Null => json.push_str("null"),
+            Value::Array(values) => {
+                json.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        json.push(',');
+                    }
+                    value.write_json(json);
+                }
+                json.push(']');
+            }
+            Value::Table(entries) => {
+                json.push('{');
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        json.push(',');
+                    }
+                    write_json_string(key, json);
+                    json.push(':');
+                    value.write_json(json);
+                }
+                json.push('}');
+            }
+        }
+    }
+}
+
+fn write_json_string(string: &str, json: &mut String) {
+    json.push('"');
+
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+}
+
+fn fail<'a, T>(input: &'a str, context: &'static str) -> ParseResult<'a, T> {
+    Err(nom::Err::Failure(VerboseError {
+        errors: vec![(input, VerboseErrorKind::Context(context))],
+    }))
+}
+
+/// A double-quoted string with backslash escapes, as both formats have it.
+fn quoted_string(input: &str) -> ParseResult<'_, String> {
+    let (mut rest, _) = char('"')(input)?;
+    let mut string = String::new();
+
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            None | Some('\n') => return fail(rest, "end of string"),
+            Some('"') => return Ok((chars.as_str(), string)),
+            Some('\\') => {
+                let escaped = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => match unicode_escape(chars.as_str()) {
+                        Some((c, after)) => {
+                            chars = after.chars();
+                            c
+                        }
+                        None => return fail(rest, "unicode escape"),
+                    },
+                    _ => return fail(rest, "escape sequence"),
+                };
+
+                string.push(escaped);
+            }
+            Some(c) => string.push(c),
+        }
+
+        rest = chars.as_str();
+    }
+}
+
+/// The four hex digits of a `\u` escape, along with those of a second
+/// escape when the first is the high half of a UTF-16 surrogate pair.
+fn unicode_escape(input: &str) -> Option<(char, &str)> {
+    fn code_unit(input: &str) -> Option<(u32, &str)> {
+        let digits = input.get(..4)?;
+        match digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            true => Some((u32::from_str_radix(digits, 16).ok()?, &input[4..])),
+            false => None,
+        }
+    }
+
+    let (high, rest) = code_unit(input)?;
+    if !(0xd800..0xdc00).contains(&high) {
+        return Some((char::from_u32(high)?, rest));
+    }
+
+    let (low, rest) = code_unit(rest.strip_prefix("\\u")?)?;
+    if !(0xdc00..0xe000).contains(&low) {
+        return None;
+    }
+
+    let c = char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))?;
+    Some((c, rest))
+}
+
+fn integer(input: &str) -> ParseResult<'_, i64> {
+    map_res(
+        recognize(pair(
+            opt(alt((char('-'), char('+')))),
+            take_while1(|c: char| c.is_ascii_digit() || c == '_'),
+        )),
+        |digits: &str| digits.replace('_', "").parse(),
+    )(input)
+}
+
+fn boolean(input: &str) -> ParseResult<'_, bool> {
+    alt((value(true, tag("true")), value(false, tag("false"))))(input)
+}
+
+/// How deeply arrays and objects may nest. Parsing recurses into each, so
+/// without a limit a request body could overflow the stack.
+const MAX_DEPTH: usize = 32;
+
+/// Fails once `depth` levels of arrays or objects have been opened.
+fn check_depth(input: &str, depth: usize) -> ParseResult<'_, ()> {
+    match depth > MAX_DEPTH {
+        true => fail(input, "nesting depth"),
+        false => Ok((input, ())),
+    }
+}
+
+fn json_value(input: &str, depth: usize) -> ParseResult<'_, Value> {
+    delimited(
+        multispace0,
+        context(
+            "value",
+            alt((
+                map(quoted_string, Value::String),
+                json_number,
+                map(boolean, Value::Bool),
+                value(Value::Null, tag("null")),
+                map(|input| json_array(input, depth + 1), Value::Array),
+                map(|input| json_object(input, depth + 1), Value::Table),
+            )),
+        ),
+        multispace0,
+    )(input)
+}
+
+/// Integers that fit an `i64` are read as such, and any other number is kept
+/// as written.
+fn json_number(input: &str) -> ParseResult<'_, Value> {
+    map(
+        recognize(tuple((
+            opt(char('-')),
+            alt((tag("0"), digit1)),
+            opt(pair(char('.'), cut(digit1))),
+            opt(tuple((
+                alt((char('e'), char('E'))),
+                opt(alt((char('+'), char('-')))),
+                cut(digit1),
+            ))),
+        ))),
+        |number: &str| match number.parse() {
+            Ok(integer) => Value::Integer(integer),
+            Err(_) => Value::Number(number.to_string()),
+        },
+    )(input)
+}
+
+fn json_array(input: &str, depth: usize) -> ParseResult<'_, Vec<Value>> {
+    let (input, _) = char('[')(input)?;
+    let (input, _) = check_depth(input, depth)?;
+
+    cut(terminated(
+        separated_list0(char(','), |input| json_value(input, depth)),
+        preceded(multispace0, context("end of array", char(']'))),
+    ))(input)
+}
+
+fn json_object(input: &str, depth: usize) -> ParseResult<'_, Vec<(String, Value)>> {
+    let (input, _) = char('{')(input)?;
+    let (input, _) = check_depth(input, depth)?;
+
+    // Once a key has been read, anything but a colon and a value is an
+    // error in that member rather than the end of the object.
+    let member = separated_pair(
+        preceded(multispace0, quoted_string),
+        cut(preceded(multispace0, context("colon", char(':')))),
+        cut(|input| json_value(input, depth)),
+    );
+
+    cut(terminated(
+        separated_list0(char(','), member),
+        preceded(multispace0, context("end of object", char('}'))),
+    ))(input)
+}
+
+fn toml_space(input: &str) -> ParseResult<'_, &str> {
+    take_while(|c| c == ' ' || c == '\t')(input)
+}
+
+/// Blank lines and comments, which may also appear inside arrays.
+fn toml_filler(input: &str) -> ParseResult<'_, ()> {
+    value(
+        (),
+        many0(alt((
+            take_while1(|c: char| c.is_whitespace()),
+            recognize(pair(char('#'), not_line_ending)),
+        ))),
+    )(input)
+}
+
+fn toml_key(input: &str) -> ParseResult<'_, Vec<String>> {
+    let bare = map(
+        take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+        String::from,
+    );
+
+    context(
+        "key",
+        separated_list1(
+            char('.'),
+            delimited(toml_space, alt((bare, quoted_string)), toml_space),
+        ),
+    )(input)
+}
+
+fn toml_value(input: &str, depth: usize) -> ParseResult<'_, Value> {
+    let literal_string = delimited(
+        char('\''),
+        take_while(|c| c != '\'' && c != '\n'),
+        char('\''),
+    );
+    let array = preceded(
+        pair(char('['), |input| check_depth(input, depth + 1)),
+        cut(terminated(
+            separated_list0(
+                char(','),
+                delimited(
+                    toml_filler,
+                    |input| toml_value(input, depth + 1),
+                    toml_filler,
+                ),
+            ),
+            // A trailing comma is allowed before the closing bracket.
+            tuple((
+                opt(char(',')),
+                toml_filler,
+                context("end of array", char(']')),
+            )),
+        )),
+    );
+
+    context(
+        "value",
+        alt((
+            map(quoted_string, Value::String),
+            map(literal_string, |string: &str| Value::String(string.into())),
+            map(boolean, Value::Bool),
+            map(integer, Value::Integer),
+            map(array, Value::Array),
+        )),
+    )(input)
+}
+
+fn toml_line_end(input: &str) -> ParseResult<'_, ()> {
+    value(
+        (),
+        tuple((
+            toml_space,
+            opt(pair(char('#'), not_line_ending)),
+            context("end of line", alt((line_ending, eof))),
+        )),
+    )(input)
+}
+
+fn toml_document(mut input: &str) -> ParseResult<'_, Vec<(String, Value)>> {
+    let mut entries = Vec::new();
+    let mut table = Vec::new();
+
+    loop {
+        (input, _) = toml_filler(input)?;
+        if input.is_empty() {
+            return Ok((input, entries));
+        }
+
+        if input.starts_with('[') {
+            (input, table) = cut(terminated(
+                delimited(
+                    char('['),
+                    toml_key,
+                    context("end of table header", char(']')),
+                ),
+                toml_line_end,
+            ))(input)?;
+            continue;
+        }
+
+        let (rest, (key, value)) = cut(terminated(
+            separated_pair(
+                toml_key,
+                context("equals sign", char('=')),
+                preceded(toml_space, |input| toml_value(input, 0)),
+            ),
+            toml_line_end,
+        ))(input)?;
+
+        let key = table.iter().chain(key.iter()).cloned().collect::<Vec<_>>();
+        entries.push((key.join("."), value));
+        input = rest;
+    }
+}
+
+/// Turns nested JSON objects into dotted keys.
+fn flatten(prefix: &str, table: Vec<(String, Value)>, entries: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let key = match prefix {
+            "" => key,
+            _ => format!("{}.{}", prefix, key),
+        };
+
+        match value {
+            Value::Table(table) => flatten(&key, table, entries),
+            value => entries.push((key, value)),
+        }
+    }
+}
+
+/// Describes where and why parsing failed.
+fn error_message(source: &str, err: nom::Err<VerboseError<&str>>) -> String {
+    let errors = match err {
+        nom::Err::Incomplete(_) => return "unexpected end of file".into(),
+        nom::Err::Error(err) | nom::Err::Failure(err) => err.errors,
+    };
+
+    let context = errors.iter().find_map(|(_, kind)| match kind {
+        VerboseErrorKind::Context(context) => Some(*context),
+        _ => None,
+    });
+
+    let line = errors.first().map_or(1, |(rest, _)| {
+        source[..source.len() - rest.len()].matches('\n').count() + 1
+    });
+
+    match context {
+        Some(context) => format!("invalid {} on line {}", context, line),
+        None => format!("syntax error on line {}", line),
+    }
+}
+
+pub fn parse_toml(source: &str) -> Result<Vec<(String, Value)>, String> {
+    toml_document(source)
+        .map(|(_, entries)| entries)
+        .map_err(|err| error_message(source, err))
+}
+
+pub fn parse_json(source: &str) -> Result<Vec<(String, Value)>, String> {
+    let (_, table) = terminated(
+        delimited(multispace0, |input| json_object(input, 1), multispace0),
+        context("end of file", eof),
+    )(source)
+    .map_err(|err| error_message(source, err))?;
+
+    let mut entries = Vec::new();
+    flatten("", table, &mut entries);
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(string: &str) -> Value {
+        Value::String(string.into())
+    }
+
+    #[test]
+    fn reads_toml_tables_into_dotted_keys() {
+        let source = "\
+            data_dir = '/srv'\n\
+            \n\
+            [listen]\n\
+            port = 8080\n\
+            \n\
+            [limits]\n\
+            workers = 4\n\
+            \"max.body\" = 1_024\n\
+            compression.enabled = true\n";
+
+        assert_eq!(
+            parse_toml(source),
+            Ok(vec![
+                ("data_dir".into(), string("/srv")),
+                ("listen.port".into(), Value::Integer(8080)),
+                ("limits.workers".into(), Value::Integer(4)),
+                ("limits.max.body".into(), Value::Integer(1024)),
+                ("limits.compression.enabled".into(), Value::Bool(true)),
+            ])
+        );
+    }
+
+    #[test]
+    fn reads_toml_arrays_with_comments_and_trailing_commas() {
+        let source = "\
+            # Where to listen\n\
+            bind = [ # one per line\n\
+              \"127.0.0.1\",\n\
+              # not this one: \"0.0.0.0\",\n\
+              \"[::1]\",\n\
+            ] # done\n\
+            empty = []\n\
+            nested = [[1, 2], []]\n";
+
+        assert_eq!(
+            parse_toml(source),
+            Ok(vec![
+                (
+                    "bind".into(),
+                    Value::Array(vec![string("127.0.0.1"), string("[::1]")])
+                ),
+                ("empty".into(), Value::Array(vec![])),
+                (
+                    "nested".into(),
+                    Value::Array(vec![
+                        Value::Array(vec![Value::Integer(1), Value::Integer(2)]),
+                        Value::Array(vec![]),
+                    ])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn keeps_repeated_toml_keys_for_the_caller_to_reject() {
+        assert_eq!(
+            parse_toml("[a]\nb = 1\n[a]\nb = 2\n"),
+            Ok(vec![
+                ("a.b".into(), Value::Integer(1)),
+                ("a.b".into(), Value::Integer(2)),
+            ])
+        );
+    }
+
+    #[test]
+    fn flattens_json_objects_into_dotted_keys() {
+        let source = r#"{
+            "listen": {"port": 8080, "bind": ["127.0.0.1", "::1"]},
+            "timeouts": {"header": 10, "body": null},
+            "compression": {"enabled": false},
+            "data_dir": "/srv"
+        }"#;
+
+        assert_eq!(
+            parse_json(source),
+            Ok(vec![
+                ("listen.port".into(), Value::Integer(8080)),
+                (
+                    "listen.bind".into(),
+                    Value::Array(vec![string("127.0.0.1"), string("::1")])
+                ),
+                ("timeouts.header".into(), Value::Integer(10)),
+                ("timeouts.body".into(), Value::Null),
+                ("compression.enabled".into(), Value::Bool(false)),
+                ("data_dir".into(), string("/srv")),
+            ])
+        );
+    }
+
+    #[test]
+    fn decodes_string_escapes() {
+        assert_eq!(
+            parse_json(r#"{"a": "q\"b\\s\/n\nt\tué"}"#),
+            Ok(vec![("a".into(), string("q\"b\\s/n\nt\tu\u{e9}"))])
+        );
+    }
+
+    #[test]
+    fn keeps_json_numbers_that_are_not_integers_as_written() {
+        let number = |source: &str| {
+            parse_json(&format!("{{\"n\": {}}}", source)).map(|entries| entries[0].1.clone())
+        };
+
+        assert_eq!(number("42"), Ok(Value::Integer(42)));
+        assert_eq!(number("-7"), Ok(Value::Integer(-7)));
+        assert_eq!(number("0.5"), Ok(Value::Number("0.5".into())));
+        assert_eq!(number("1e3"), Ok(Value::Number("1e3".into())));
+        assert_eq!(number("-2.5E+10"), Ok(Value::Number("-2.5E+10".into())));
+        assert_eq!(
+            number("99999999999999999999"),
+            Ok(Value::Number("99999999999999999999".into()))
+        );
+
+        for malformed in ["01", "1.", ".5", "1e", "+1", "- 1"] {
+            assert!(number(malformed).is_err(), "{}", malformed);
+        }
+
+        assert_eq!(Value::Number("1.50".into()).to_json(), "1.50");
+    }
+
+    #[test]
+    fn decodes_surrogate_pairs() {
+        assert_eq!(
+            parse_json(r#"{"a": "\ud83d\ude00 \uD834\uDD1E"}"#),
+            Ok(vec![("a".into(), string("\u{1f600} \u{1d11e}"))])
+        );
+
+        for lone in [r"\ud83d", r"\ud83dx", r"\ud83d\u0041", r"\ude00", r"\u12"] {
+            assert_eq!(
+                parse_json(&format!("{{\"a\": \"{}\"}}", lone)),
+                Err("invalid unicode escape on line 1".into()),
+                "{}",
+                lone
+            );
+        }
+    }
+
+    #[test]
+    fn says_where_parsing_failed() {
+        assert_eq!(
+            parse_toml("a = 1\nb = \n"),
+            Err("invalid value on line 2".into())
+        );
+        assert_eq!(
+            parse_toml("a = 1\n[b\n"),
+            Err("invalid end of table header on line 2".into())
+        );
+        assert_eq!(
+            parse_toml("a = [1, 2\n"),
+            Err("invalid end of array on line 2".into())
+        );
+        assert_eq!(
+            parse_toml("a = 1 2\n"),
+            Err("invalid end of line on line 1".into())
+        );
+        assert_eq!(
+            parse_toml("a = \"open\n"),
+            Err("invalid end of string on line 1".into())
+        );
+        assert_eq!(
+            parse_json("{\n\"a\": 1,\n\"b\" 2}"),
+            Err("invalid colon on line 3".into())
+        );
+        assert_eq!(
+            parse_json("{\"a\": 1} x"),
+            Err("invalid end of file on line 1".into())
+        );
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let deep = format!("{{\"a\": {}1{}}}", "[".repeat(40), "]".repeat(40));
+        assert_eq!(
+            parse_json(&deep),
+            Err("invalid nesting depth on line 1".into())
+        );
+
+        let deep = format!("a = {}1{}", "[".repeat(40), "]".repeat(40));
+        assert_eq!(
+            parse_toml(&deep),
+            Err("invalid nesting depth on line 1".into())
+        );
+    }
+}
@@ -0,0 +1,728 @@
+//! Server settings, read from a configuration file, the environment and the
+//! command line, in increasing order of precedence.
+
+pub mod format;
+
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    connection::Timeouts,
+    listener::{ListenAddr, DEFAULT_PORT},
+    log::LogLevel,
+    pool::{PoolConfig, QueueFullPolicy},
+    router::{ErrorFormat, RouterKind},
+};
+use format::Value;
+
+pub struct Config {
+    pub listen: Vec<ListenAddr>,
+    pub data_dir: PathBuf,
+    pub max_upload_size: usize,
+    /// The largest body read for routes that set no limit of their own.
+    pub max_body_size: usize,
+    pub pool: PoolConfig,
+    pub timeouts: Timeouts,
+    /// How long in-flight requests get to finish once shutdown starts.
+    pub grace_period: Duration,
+    /// Whether responses may be gzip-encoded for clients that accept it.
+    pub compression: bool,
+    pub log_level: LogLevel,
+    /// How errors returned by handlers are rendered.
+    pub error_format: ErrorFormat,
+    pub router: RouterKind,
+    /// Further sites served alongside the default one.
+    pub sites: Vec<Site>,
+}
+
+/// A site served to requests for `host`, exactly or, as in `*.example.com`,
+/// by suffix, with files from its own data directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Site {
+    pub host: String,
+    pub data_dir: PathBuf,
+}
+
+/// What the command line asked for.
+pub enum Command {
+    Serve(Box<Config>),
+    Help,
+    Version,
+}
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A setting that can be given in the configuration file, under `key`, and
+/// on the command line as `flag`.
+struct Setting {
+    key: &'static str,
+    flag: &'static str,
+    env: Option<&'static str>,
+    arg: &'static str,
+    help: &'static str,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "listen.bind",
+        flag: "--bind",
+        env: Some("HTTP_BIND"),
+        arg: "ADDR",
+        help: "Address to listen on, e.g. 0.0.0.0, [::]:8080 or unix:/run/http.sock. \
+               May be repeated or comma-separated [default: 127.0.0.1]",
+    },
+    Setting {
+        key: "listen.port",
+        flag: "--port",
+        env: Some("HTTP_PORT"),
+        arg: "PORT",
+        help: "Port for bind addresses that do not name one [default: 4221]",
+    },
+    Setting {
+        key: "listen.unix_socket",
+        flag: "--unix-socket",
+        env: Some("HTTP_UNIX_SOCKET"),
+        arg: "PATH",
+        help: "Also listen on a Unix domain socket at PATH",
+    },
+    Setting {
+        key: "data_dir",
+        flag: "--directory",
+        env: None,
+        arg: "DIR",
+        help: "Directory files are served from and uploaded to [default: current directory]",
+    },
+    Setting {
+        key: "sites",
+        flag: "--site",
+        env: None,
+        arg: "HOST=DIR",
+        help: "Also serve requests for HOST, or for any subdomain with *.HOST, with files \
+               from DIR. May be repeated or comma-separated",
+    },
+    Setting {
+        key: "limits.workers",
+        flag: "--workers",
+        env: None,
+        arg: "N",
+        help: "Worker threads [default: number of CPUs]",
+    },
+    Setting {
+        key: "limits.max_connections",
+        flag: "--max-connections",
+        env: None,
+        arg: "N",
+        help: "Connections served at once [default: 10000]",
+    },
+    Setting {
+        key: "limits.queue_capacity",
+        flag: "--queue-capacity",
+        env: None,
+        arg: "N",
+        help: "Connections allowed to wait for a free slot [default: 256]",
+    },
+    Setting {
+        key: "limits.reject_when_full",
+        flag: "--reject-when-full",
+        env: None,
+        arg: "SECS",
+        help: "Turn connections away with 503 while full and the queue is too, asking \
+               clients to retry after SECS, instead of holding them",
+    },
+    Setting {
+        key: "limits.max_upload_size",
+        flag: "--max-upload-size",
+        env: None,
+        arg: "BYTES",
+        help: "Largest accepted file upload [default: 67108864]",
+    },
+    Setting {
+        key: "limits.max_body_size",
+        flag: "--max-body-size",
+        env: None,
+        arg: "BYTES",
+        help: "Largest request body accepted by routes without a limit of their own [default: 1048576]",
+    },
+    Setting {
+        key: "timeouts.header",
+        flag: "--header-timeout",
+        env: None,
+        arg: "SECS",
+        help: "Time allowed to send a request's header section [default: 10]",
+    },
+    Setting {
+        key: "timeouts.body",
+        flag: "--body-timeout",
+        env: None,
+        arg: "SECS",
+        help: "Time allowed to send a request's body [default: 60]",
+    },
+    Setting {
+        key: "timeouts.keep_alive",
+        flag: "--keep-alive-timeout",
+        env: None,
+        arg: "SECS",
+        help: "How long idle connections are kept open [default: 5]",
+    },
+    Setting {
+        key: "timeouts.write",
+        flag: "--write-timeout",
+        env: None,
+        arg: "SECS",
+        help: "Time allowed to write out a response [default: 30]",
+    },
+    Setting {
+        key: "timeouts.grace_period",
+        flag: "--grace-period",
+        env: None,
+        arg: "SECS",
+        help: "Time in-flight requests get to finish on shutdown [default: 30]",
+    },
+    Setting {
+        key: "compression.enabled",
+        flag: "--compression",
+        env: None,
+        arg: "on|off",
+        help: "Gzip-encode responses for clients that accept it [default: on]",
+    },
+    Setting {
+        key: "logging.level",
+        flag: "--log-level",
+        env: None,
+        arg: "LEVEL",
+        help: "One of off, error, warn, info or debug [default: info]",
+    },
+    Setting {
+        key: "errors.format",
+        flag: "--error-format",
+        env: None,
+        arg: "FORMAT",
+        help:
+            "Render handler errors as text, json (RFC 9457 problem details) or html [default: text]",
+    },
+    Setting {
+        key: "routing.router",
+        flag: "--router",
+        env: None,
+        arg: "KIND",
+        help: "Route with a trie, preferring static segments to params, or with regexes tried \
+               in the order routes were added [default: trie]",
+    },
+];
+
+/// Settings whose flags may be repeated, adding up to a list.
+const REPEATABLE: &[&str] = &["listen.bind", "sites"];
+
+/// Settings as given, before listen addresses are resolved against the port.
+struct Settings {
+    bind: Vec<String>,
+    port: u16,
+    unix_socket: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+    max_upload_size: usize,
+    max_body_size: usize,
+    pool: PoolConfig,
+    timeouts: Timeouts,
+    grace_period: Duration,
+    compression: bool,
+    log_level: LogLevel,
+    error_format: ErrorFormat,
+    router: RouterKind,
+    sites: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            bind: vec![],
+            port: DEFAULT_PORT,
+            unix_socket: None,
+            data_dir: None,
+            max_upload_size: 64 * 1024 * 1024,
+            max_body_size: 1024 * 1024,
+            pool: PoolConfig::default(),
+            timeouts: Timeouts::default(),
+            grace_period: Duration::from_secs(30),
+            compression: true,
+            log_level: LogLevel::Info,
+            error_format: ErrorFormat::Text,
+            router: RouterKind::Trie,
+            sites: vec![],
+        }
+    }
+}
+
+fn string(value: Value) -> Result<String, String> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err("expected a string".into()),
+    }
+}
+
+/// Values from the command line and environment arrive as strings, so
+/// those are accepted wherever another type is expected.
+fn integer<T: TryFrom<i64>>(value: Value) -> Result<T, String> {
+    let integer = match value {
+        Value::Integer(integer) => integer,
+        Value::String(string) => string
+            .trim()
+            .parse()
+            .map_err(|_| format!("expected an integer, got {:?}", string))?,
+        _ => return Err("expected an integer".into()),
+    };
+
+    T::try_from(integer).map_err(|_| format!("{} is out of range", integer))
+}
+
+fn positive(value: Value) -> Result<usize, String> {
+    match integer(value)? {
+        0 => Err("must be greater than zero".into()),
+        n => Ok(n),
+    }
+}
+
+fn seconds(value: Value) -> Result<Duration, String> {
+    integer(value).map(Duration::from_secs)
+}
+
+fn boolean(value: Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(boolean) => Ok(boolean),
+        Value::String(string) => match string.to_lowercase().as_str() {
+            "true" | "on" | "yes" => Ok(true),
+            "false" | "off" | "no" => Ok(false),
+            _ => Err(format!("expected on or off, got {:?}", string)),
+        },
+        _ => Err("expected a boolean".into()),
+    }
+}
+
+/// A list given either as an array or as a comma-separated string.
+fn list(value: Value) -> Result<Vec<String>, String> {
+    let items = match value {
+        Value::Array(items) => items.into_iter().map(string).collect::<Result<_, _>>()?,
+        Value::String(string) => string.split(',').map(String::from).collect::<Vec<_>>(),
+        _ => return Err("expected a list of strings".into()),
+    };
+
+    Ok(items
+        .into_iter()
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect())
+}
+
+impl Settings {
+    fn set(self: &mut Self, key: &str, value: Value) -> Result<(), String> {
+        if value == Value::Null {
+            return Ok(());
+        }
+
+        match key {
+            "listen.bind" => self.bind = list(value)?,
+            "listen.port" => self.port = integer(value)?,
+            "listen.unix_socket" => self.unix_socket = Some(string(value)?.into()),
+            "data_dir" => self.data_dir = Some(string(value)?.into()),
+            "limits.workers" => self.pool.workers = positive(value)?,
+            "limits.max_connections" => self.pool.max_connections = positive(value)?,
+            "limits.queue_capacity" => self.pool.queue_capacity = integer(value)?,
+            "limits.reject_when_full" => {
+                self.pool.queue_full_policy = QueueFullPolicy::Reject {
+                    retry_after: seconds(value)?,
+                }
+            }
+            "limits.max_upload_size" => self.max_upload_size = integer(value)?,
+            "limits.max_body_size" => self.max_body_size = integer(value)?,
+            "timeouts.header" => self.timeouts.header = seconds(value)?,
+            "timeouts.body" => self.timeouts.body = seconds(value)?,
+            "timeouts.keep_alive" => self.timeouts.keep_alive = seconds(value)?,
+            "timeouts.write" => self.timeouts.write = seconds(value)?,
+            "timeouts.grace_period" => self.grace_period = seconds(value)?,
+            "compression.enabled" => self.compression = boolean(value)?,
+            "logging.level" => self.log_level = string(value)?.parse()?,
+            "errors.format" => self.error_format = string(value)?.parse()?,
+            "routing.router" => self.router = string(value)?.parse()?,
+            "sites" => self.sites = list(value)?,
+            _ => return Err("unknown setting".into()),
+        }
+
+        Ok(())
+    }
+
+    fn load_file(self: &mut Self, path: &Path) -> Result<(), ConfigError> {
+        let error = |message: String| ConfigError(format!("{}: {}", path.display(), message));
+
+        let source = fs::read_to_string(path).map_err(|err| error(err.to_string()))?;
+
+        let is_json = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => true,
+            Some("toml") => false,
+            _ => source.trim_start().starts_with('{'),
+        };
+
+        let entries = match is_json {
+            true => format::parse_json(&source),
+            false => format::parse_toml(&source),
+        }
+        .map_err(error)?;
+
+        let mut seen = Vec::with_capacity(entries.len());
+        for (key, value) in entries {
+            if seen.contains(&key) {
+                return Err(error(format!("{} is set more than once", key)));
+            }
+
+            self.set(&key, value)
+                .map_err(|message| error(format!("{}: {}", key, message)))?;
+            seen.push(key);
+        }
+
+        Ok(())
+    }
+
+    fn resolve(self: Self) -> Result<Config, ConfigError> {
+        let mut listen = self
+            .bind
+            .iter()
+            .map(|addr| ListenAddr::parse(addr, self.port))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ConfigError)?;
+
+        if let Some(path) = self.unix_socket {
+            listen.push(ListenAddr::Unix(path));
+        }
+
+        if listen.is_empty() {
+            listen.push(ListenAddr::Tcp(([127, 0, 0, 1], self.port).into()));
+        }
+
+        let data_dir = match self.data_dir {
+            Some(data_dir) => data_dir,
+            None => env::current_dir().map_err(|err| {
+                ConfigError(format!("Cannot determine the current directory: {}", err))
+            })?,
+        };
+
+        let sites = self
+            .sites
+            .iter()
+            .map(|site| match site.split_once('=') {
+                Some((host, data_dir)) if !host.is_empty() && !data_dir.is_empty() => Ok(Site {
+                    host: host.to_string(),
+                    data_dir: data_dir.into(),
+                }),
+                _ => Err(ConfigError(format!(
+                    "Sites are given as HOST=DIR, got {:?}",
+                    site
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for data_dir in [&data_dir]
+            .into_iter()
+            .chain(sites.iter().map(|site| &site.data_dir))
+        {
+            if !data_dir.is_dir() {
+                return Err(ConfigError(format!(
+                    "Data directory {} does not exist",
+                    data_dir.display()
+                )));
+            }
+        }
+
+        Ok(Config {
+            listen,
+            data_dir,
+            max_upload_size: self.max_upload_size,
+            max_body_size: self.max_body_size,
+            pool: self.pool,
+            timeouts: self.timeouts,
+            grace_period: self.grace_period,
+            compression: self.compression,
+            log_level: self.log_level,
+            error_format: self.error_format,
+            router: self.router,
+            sites,
+        })
+    }
+}
+
+/// Reads the configuration from the command line `args`, not including the
+/// program name, along with the environment and any configuration file they
+/// point to.
+pub fn load(args: impl IntoIterator<Item = String>) -> Result<Command, ConfigError> {
+    let mut config_file = env::var_os("HTTP_CONFIG").map(PathBuf::from);
+    let mut flags: Vec<(&Setting, String)> = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+
+        let mut value = |arg_name: &str| {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| ConfigError(format!("{} expects a value ({})", flag, arg_name)))
+        };
+
+        match flag {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-c" | "--config" => config_file = Some(value("FILE")?.into()),
+            _ => match SETTINGS.iter().find(|setting| setting.flag == flag) {
+                Some(setting) => flags.push((setting, value(setting.arg)?)),
+                None => return Err(ConfigError(format!("Unknown argument {:?}", arg))),
+            },
+        }
+    }
+
+    let mut settings = Settings::default();
+
+    if let Some(path) = config_file {
+        settings.load_file(&path)?;
+    }
+
+    for setting in SETTINGS {
+        let Some(env) = setting.env else { continue };
+
+        if let Ok(value) = env::var(env) {
+            settings
+                .set(setting.key, Value::String(value))
+                .map_err(|message| ConfigError(format!("{}: {}", env, message)))?;
+        }
+    }
+
+    // Repeated flags such as --bind add up, replacing any values from the
+    // file or environment rather than adding to them.
+    for setting in SETTINGS
+        .iter()
+        .filter(|setting| REPEATABLE.contains(&setting.key))
+    {
+        let values = flags
+            .iter()
+            .filter(|(flag, _)| flag.key == setting.key)
+            .flat_map(|(_, values)| values.split(','))
+            .map(|value| Value::String(value.into()))
+            .collect::<Vec<_>>();
+
+        if !values.is_empty() {
+            settings
+                .set(setting.key, Value::Array(values))
+                .map_err(|message| ConfigError(format!("{}: {}", setting.flag, message)))?;
+        }
+    }
+
+    for (setting, value) in flags {
+        if REPEATABLE.contains(&setting.key) {
+            continue;
+        }
+
+        settings
+            .set(setting.key, Value::String(value))
+            .map_err(|message| ConfigError(format!("{}: {}", setting.flag, message)))?;
+    }
+
+    settings
+        .resolve()
+        .map(|config| Command::Serve(Box::new(config)))
+}
+
+pub fn usage(program: &str) -> String {
+    let mut usage = format!(
+        "Usage: {} [OPTIONS]\n\n\
+         Options:\n  \
+         -c, --config <FILE>\n          \
+         Read settings from a TOML or JSON file; flags given here override it [env: HTTP_CONFIG]\n",
+        program
+    );
+
+    for setting in SETTINGS {
+        usage.push_str(&format!(
+            "      {} <{}>\n          {}",
+            setting.flag, setting.arg, setting.help
+        ));
+
+        if let Some(env) = setting.env {
+            usage.push_str(&format!(" [env: {}]", env));
+        }
+
+        usage.push('\n');
+    }
+
+    usage.push_str(
+        "  -h, --help\n          Print this help\n  \
+         -V, --version\n          Print the version\n",
+    );
+
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Result<Config, String> {
+        match load(args.iter().map(|arg| arg.to_string())) {
+            Ok(Command::Serve(config)) => Ok(*config),
+            Ok(_) => panic!("expected a configuration"),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn error(args: &[&str]) -> String {
+        match config(args) {
+            Ok(_) => panic!("expected {:?} to be rejected", args),
+            Err(err) => err,
+        }
+    }
+
+    /// Writes a configuration file of its own for each test.
+    fn file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("config-test-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+
+        path
+    }
+
+    fn listen(config: &Config) -> Vec<String> {
+        config.listen.iter().map(ToString::to_string).collect()
+    }
+
+    /// This is the only test touching the environment, which the others
+    /// share, so they stay clear of the port.
+    #[test]
+    fn flags_beat_the_environment_which_beats_the_file() {
+        let path = file("precedence.toml", "[listen]\nport = 1000\n");
+        let path = path.to_str().unwrap();
+        let port = |args: &[&str]| config(args).unwrap().listen;
+
+        env::remove_var("HTTP_PORT");
+        assert_eq!(
+            port(&["--config", path]),
+            vec![ListenAddr::Tcp(([127, 0, 0, 1], 1000).into())]
+        );
+
+        env::set_var("HTTP_PORT", "2000");
+        assert_eq!(
+            port(&["--config", path]),
+            vec![ListenAddr::Tcp(([127, 0, 0, 1], 2000).into())]
+        );
+        assert_eq!(
+            port(&["--config", path, "--port", "3000"]),
+            vec![ListenAddr::Tcp(([127, 0, 0, 1], 3000).into())]
+        );
+        env::remove_var("HTTP_PORT");
+    }
+
+    #[test]
+    fn adds_up_repeated_and_comma_separated_binds() {
+        let config = config(&[
+            "--bind",
+            "127.0.0.1:80,[::1]:81",
+            "--bind=0.0.0.0:82",
+            "--bind",
+            " , unix:/tmp/a.sock",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            listen(&config),
+            ["127.0.0.1:80", "[::1]:81", "0.0.0.0:82", "unix:/tmp/a.sock"]
+        );
+    }
+
+    #[test]
+    fn binds_from_flags_replace_those_from_the_file() {
+        let path = file("bind.toml", "[listen]\nbind = [\"127.0.0.1:80\"]\n");
+        let path = path.to_str().unwrap();
+
+        assert_eq!(listen(&config(&["-c", path]).unwrap()), ["127.0.0.1:80"]);
+        assert_eq!(
+            listen(&config(&["-c", path, "--bind", "0.0.0.0:81"]).unwrap()),
+            ["0.0.0.0:81"]
+        );
+    }
+
+    #[test]
+    fn reads_settings_from_json_files() {
+        let path = file(
+            "settings.json",
+            r#"{"limits": {"workers": 3}, "timeouts": {"header": 7}, "compression": {"enabled": false}}"#,
+        );
+        let config = config(&["-c", path.to_str().unwrap()]).unwrap();
+
+        assert_eq!(config.pool.workers, 3);
+        assert_eq!(config.timeouts.header, Duration::from_secs(7));
+        assert!(!config.compression);
+    }
+
+    #[test]
+    fn rejects_settings_given_twice_in_a_file() {
+        let path = file(
+            "twice.toml",
+            "[limits]\nworkers = 1\n\n[limits]\nworkers = 2\n",
+        );
+        let err = error(&["-c", path.to_str().unwrap()]);
+
+        assert_eq!(
+            err,
+            format!("{}: limits.workers is set more than once", path.display())
+        );
+    }
+
+    #[test]
+    fn explains_what_is_wrong() {
+        let path = file("errors.toml", "[limits]\nworkers = 0\n");
+        assert_eq!(
+            error(&["-c", path.to_str().unwrap()]),
+            format!(
+                "{}: limits.workers: must be greater than zero",
+                path.display()
+            )
+        );
+
+        let path = file("unknown.toml", "colour = \"blue\"\n");
+        assert_eq!(
+            error(&["-c", path.to_str().unwrap()]),
+            format!("{}: colour: unknown setting", path.display())
+        );
+
+        let path = file("syntax.toml", "[limits]\nworkers = \n");
+        assert_eq!(
+            error(&["-c", path.to_str().unwrap()]),
+            format!("{}: invalid value on line 2", path.display())
+        );
+
+        assert_eq!(
+            error(&["--workers", "many"]),
+            "--workers: expected an integer, got \"many\""
+        );
+        assert_eq!(
+            error(&["--compression", "maybe"]),
+            "--compression: expected on or off, got \"maybe\""
+        );
+        assert_eq!(error(&["--workers"]), "--workers expects a value (N)");
+        assert_eq!(error(&["--colour"]), "Unknown argument \"--colour\"");
+        assert_eq!(
+            error(&["--bind", "nowhere"]),
+            "Invalid listen address \"nowhere\""
+        );
+        assert_eq!(
+            error(&["--site", "example.com"]),
+            "Sites are given as HOST=DIR, got \"example.com\""
+        );
+    }
+}
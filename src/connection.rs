@@ -0,0 +1,469 @@
+use std::time::Duration;
+
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite},
+    time::{self, Instant},
+};
+
+use crate::{
+    listener::Connection,
+    request::{HttpConnectionReader, HttpMethod, HttpReadError, HttpRequestReader},
+    response::{HttpResponse, HttpResponseBuilder, HttpResponseWriter},
+    router::{HttpError, HttpRouter},
+    shutdown::ShutdownSignal,
+};
+
+/// How long a connection may take over each stage of an exchange before it
+/// is given up on.
+#[derive(Clone, Debug)]
+pub struct Timeouts {
+    /// From the first byte of a request until its header section is complete.
+    pub header: Duration,
+    /// From the end of the header section until the body is complete.
+    pub body: Duration,
+    /// How long an open connection may sit between requests.
+    pub keep_alive: Duration,
+    /// From the start of a response until it has been written out entirely.
+    pub write: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            header: Duration::from_secs(10),
+            body: Duration::from_secs(60),
+            keep_alive: Duration::from_secs(5),
+            write: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Writes a response, marking it as the last one on the connection unless
+/// `keep_alive` is set. Returns whether the write succeeded.
+async fn send(
+    stream: &mut (impl AsyncWrite + Unpin),
+    mut res: HttpResponse,
+    keep_alive: bool,
+    timeouts: &Timeouts,
+) -> bool {
+    if !keep_alive {
+        res.headers.insert("connection".into(), "close".into());
+    }
+
+    match time::timeout(timeouts.write, stream.write_http_res(res)).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(err)) => {
+            warn!("Failed to write response: {}", err);
+            false
+        }
+        Err(_) => {
+            warn!("Timed out writing response");
+            false
+        }
+    }
+}
+
+/// Answers a request that could not be read, where there is anyone left to
+/// answer.
+async fn read_failed(
+    stream: &mut (impl AsyncWrite + Unpin),
+    err: HttpReadError,
+    stage: &str,
+    timeouts: &Timeouts,
+) {
+    let res = match err {
+        HttpReadError::Malformed(err) => {
+            info!("Received invalid request: {:?}", err.message());
+
+            HttpResponseBuilder::default()
+                .status(400, Some("Bad Request"))
+                .build()
+        }
+        HttpReadError::UnsupportedVersion((major, minor)) => {
+            info!("Received request for unsupported HTTP/{}.{}", major, minor);
+
+            HttpResponseBuilder::default()
+                .status(505, Some("HTTP Version Not Supported"))
+                .build()
+        }
+        HttpReadError::TooLarge => {
+            info!("Request body too large");
+
+            HttpResponseBuilder::default()
+                .status(413, Some("Content Too Large"))
+                .build()
+        }
+        HttpReadError::TimedOut => {
+            info!("Timed out waiting for request {}", stage);
+
+            HttpResponseBuilder::default()
+                .status(408, Some("Request Timeout"))
+                .build()
+        }
+        HttpReadError::Closed => return,
+        HttpReadError::Io(err) => {
+            error!("{}", err);
+            return;
+        }
+    };
+
+    send(stream, res, false, timeouts).await;
+}
+
+/// Serves one request off the connection, returning whether the connection
+/// can be reused for another one.
+async fn serve_request(
+    router: &impl HttpRouter,
+    reader: &mut HttpConnectionReader<impl AsyncRead + Unpin>,
+    stream: &mut (impl AsyncWrite + Unpin),
+    timeouts: &Timeouts,
+    max_body_size: usize,
+    idle_timeout: Duration,
+    shutdown: &mut ShutdownSignal,
+) -> bool {
+    reader.set_deadline(Some(Instant::now() + idle_timeout));
+
+    // Idle connections are closed as soon as shutdown starts; requests that
+    // have already begun arriving are seen through.
+    let waited = tokio::select! {
+        waited = reader.wait_for_request() => waited,
+        _ = shutdown.triggered() => return false,
+    };
+
+    match waited {
+        Ok(true) => {}
+        Ok(false) => return false,
+        Err(HttpReadError::TimedOut) => {
+            debug!("Closing idle connection");
+            return false;
+        }
+        Err(err) => {
+            read_failed(stream, err, "head", timeouts).await;
+            return false;
+        }
+    }
+
+    reader.set_deadline(Some(Instant::now() + timeouts.header));
+    let (mut req, body) = match reader.read_http_head().await {
+        Ok(Some(head)) => head,
+        Ok(None) => return false,
+        Err(err) => {
+            read_failed(stream, err, "head", timeouts).await;
+            return false;
+        }
+    };
+
+    let keep_alive = req.keep_alive();
+    let is_http_1_0 = req.version == (1, 0);
+    let is_head = req.method == HttpMethod::HEAD;
+
+    // HTTP/1.0 clients do not understand 100 Continue, so their expectations
+    // are ignored altogether (RFC 9110, section 10.1.1).
+    let expectation = match req.version {
+        (1, 0) => None,
+        _ => req
+            .headers
+            .get("expect")
+            .map(|expect| expect.to_lowercase()),
+    };
+
+    let has_body = !body.is_empty();
+
+    // Bodies announced as too large are turned down before the client is
+    // told to send them.
+    let precheck = match expectation.as_deref() {
+        None | Some("100-continue") => router.precheck(&req),
+        Some(_) => Err(HttpError::status(417)),
+    }
+    .map(|limit| body.max_size(limit.unwrap_or(max_body_size)))
+    .and_then(|body| match body.fits() {
+        true => Ok(body),
+        false => Err(HttpError::status(413)),
+    });
+
+    // A request turned down here leaves its body unread, where it would be
+    // taken for the next request, so then the connection cannot be reused.
+    let (mut res, body_unread) = match precheck {
+        Err(err) => (router.reject(req, err).await, has_body),
+        Ok(body) => {
+            if expectation.is_some() {
+                let res = HttpResponseBuilder::default()
+                    .status(100, Some("Continue"))
+                    .build();
+
+                if !send(stream, res, true, timeouts).await {
+                    return false;
+                }
+            }
+
+            reader.set_deadline(Some(Instant::now() + timeouts.body));
+            req.body = match reader.read_http_body(body).await {
+                Ok(body) => body,
+                Err(err) => {
+                    read_failed(stream, err, "body", timeouts).await;
+                    return false;
+                }
+            };
+
+            (router.dispatch(req).await, false)
+        }
+    };
+
+    // HEAD is answered with the headers a GET would get, content-length
+    // included, but never a body (RFC 9110, section 9.3.2).
+    if is_head {
+        res.body = None;
+    }
+
+    let keep_alive = keep_alive && !body_unread && !shutdown.is_triggered();
+    if keep_alive && is_http_1_0 {
+        res.headers.insert("connection".into(), "keep-alive".into());
+    }
+
+    send(stream, res, keep_alive, timeouts).await && keep_alive
+}
+
+/// Serves requests off a connection until either side closes it. Bodies
+/// are limited to `max_body_size` bytes unless their endpoint sets a limit
+/// of its own.
+///
+/// Requests are handled one at a time, so responses to pipelined requests
+/// are always written in the order the requests arrived.
+pub async fn serve_connection(
+    router: &impl HttpRouter,
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    timeouts: &Timeouts,
+    max_body_size: usize,
+    mut shutdown: ShutdownSignal,
+) {
+    let (read_half, mut write_half) = io::split(stream);
+    let mut reader = HttpConnectionReader::new(read_half);
+
+    // A freshly accepted connection gets as long to start its first request
+    // as it gets to finish sending a header section.
+    let mut idle_timeout = timeouts.header;
+    while serve_request(
+        router,
+        &mut reader,
+        &mut write_half,
+        timeouts,
+        max_body_size,
+        idle_timeout,
+        &mut shutdown,
+    )
+    .await
+    {
+        idle_timeout = timeouts.keep_alive;
+    }
+}
+
+/// Turns a connection away because the server is at capacity.
+pub async fn reject_connection(mut stream: Connection, retry_after: Duration, timeouts: &Timeouts) {
+    let res = HttpResponseBuilder::default()
+        .status(503, Some("Service Unavailable"))
+        .header("Retry-After", retry_after.as_secs().max(1).to_string())
+        .build();
+
+    send(&mut stream, res, false, timeouts).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        task::JoinHandle,
+    };
+
+    use crate::{
+        request::HttpRequest,
+        router::{HttpRegexEndpoint, RegexRouter},
+        shutdown,
+    };
+
+    use super::*;
+
+    fn router() -> RegexRouter {
+        RegexRouter::new(vec![
+            HttpRegexEndpoint::new(HttpMethod::GET, "/fast", || "fast"),
+            HttpRegexEndpoint::new_async(HttpMethod::GET, "/slow", || async {
+                time::sleep(Duration::from_millis(20)).await;
+                "slow"
+            }),
+            HttpRegexEndpoint::new(HttpMethod::POST, "/echo", |req: HttpRequest| {
+                req.body.unwrap_or_default().to_vec()
+            }),
+            HttpRegexEndpoint::new(HttpMethod::GET, "/big", || vec![b'x'; 64 * 1024]),
+        ])
+        .unwrap()
+    }
+
+    /// Timeouts short enough for tests to run into.
+    const SHORT: Timeouts = Timeouts {
+        header: Duration::from_millis(50),
+        body: Duration::from_millis(50),
+        keep_alive: Duration::from_millis(50),
+        write: Duration::from_millis(50),
+    };
+
+    /// Opens a connection to a server buffering up to `buffer` bytes each
+    /// way, returning the client's end and the server's task.
+    fn connect(timeouts: Timeouts, buffer: usize) -> (io::DuplexStream, JoinHandle<()>) {
+        let (client, server) = io::duplex(buffer);
+        let (trigger, shutdown) = shutdown::channel();
+
+        let server = tokio::spawn(async move {
+            serve_connection(&router(), server, &timeouts, 1024, shutdown).await;
+            // Dropping the trigger would count as shutting down.
+            drop(trigger);
+        });
+
+        (client, server)
+    }
+
+    /// Sends `input` and reads everything written back until the server
+    /// hangs up, which it must do within a second. The sending side is
+    /// closed after `input` unless `hold_open` is set.
+    async fn talk(timeouts: Timeouts, input: &[u8], hold_open: bool) -> String {
+        let (mut client, server) = connect(timeouts, 64 * 1024);
+
+        client.write_all(input).await.unwrap();
+        if !hold_open {
+            client.shutdown().await.unwrap();
+        }
+
+        let mut output = Vec::new();
+        time::timeout(Duration::from_secs(1), client.read_to_end(&mut output))
+            .await
+            .expect("the server kept the connection open")
+            .unwrap();
+        server.await.unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Sends `input` over a fresh connection and closes the sending side,
+    /// returning everything written back.
+    async fn exchange(input: &[u8]) -> String {
+        talk(Timeouts::default(), input, false).await
+    }
+
+    /// The status line and body of each response in `output`, in order.
+    fn responses(output: &str) -> Vec<(&str, &str)> {
+        output
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .map(|res| {
+                let (head, body) = res.split_once("\r\n\r\n").unwrap();
+                (head.lines().next().unwrap(), body)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn answers_pipelined_requests_in_order() {
+        let output = exchange(
+            b"GET /slow HTTP/1.1\r\nHost: x\r\n\r\n\
+            GET /fast HTTP/1.1\r\nHost: x\r\n\r\n\
+            POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\nabc\
+            GET /fast HTTP/1.1\r\nHost: x\r\n\r\n",
+        )
+        .await;
+
+        assert_eq!(
+            responses(&output),
+            vec![
+                ("200 OK", "slow"),
+                ("200 OK", "fast"),
+                ("200 OK", "abc"),
+                ("200 OK", "fast"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn closes_after_rejecting_a_request_with_an_unread_body() {
+        let output = exchange(
+            b"POST /fast HTTP/1.1\r\nHost: x\r\nContent-Length: 18\r\n\r\n\
+            GET /fast HTTP/1.1\r\nGET /fast HTTP/1.1\r\nHost: x\r\n\r\n",
+        )
+        .await;
+
+        assert_eq!(responses(&output), vec![("405 Method Not Allowed", "")]);
+        assert!(output.contains("connection: close\r\n"));
+    }
+
+    #[tokio::test]
+    async fn keeps_the_connection_after_rejecting_a_request_without_a_body() {
+        let output = exchange(
+            b"POST /fast HTTP/1.1\r\nHost: x\r\n\r\n\
+            GET /fast HTTP/1.1\r\nHost: x\r\n\r\n",
+        )
+        .await;
+
+        assert_eq!(
+            responses(&output),
+            vec![("405 Method Not Allowed", ""), ("200 OK", "fast")]
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_unsupported_versions_with_505() {
+        for version in ["2.0", "0.9", "1.2"] {
+            let input = format!("GET /fast HTTP/{}\r\nHost: x\r\n\r\n", version);
+            let output = exchange(input.as_bytes()).await;
+
+            assert_eq!(
+                responses(&output),
+                vec![("505 HTTP Version Not Supported", "")],
+                "{}",
+                version
+            );
+        }
+
+        let output = exchange(b"GET /fast HTTP/1.0\r\n\r\n").await;
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn times_out_waiting_for_the_header_section() {
+        let output = talk(SHORT, b"GET /fast HTTP/1.1\r\nHo", true).await;
+
+        assert_eq!(responses(&output), vec![("408 Request Timeout", "")]);
+        assert!(output.contains("connection: close\r\n"));
+    }
+
+    #[tokio::test]
+    async fn times_out_waiting_for_the_body() {
+        let output = talk(
+            SHORT,
+            b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nab",
+            true,
+        )
+        .await;
+
+        assert_eq!(responses(&output), vec![("408 Request Timeout", "")]);
+    }
+
+    #[tokio::test]
+    async fn closes_idle_connections_quietly() {
+        assert_eq!(talk(SHORT, b"", true).await, "");
+
+        let output = talk(SHORT, b"GET /fast HTTP/1.1\r\nHost: x\r\n\r\n", true).await;
+        assert_eq!(responses(&output), vec![("200 OK", "fast")]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_clients_that_stop_reading() {
+        let (mut client, server) = connect(SHORT, 1024);
+
+        client
+            .write_all(b"GET /big HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+
+        time::timeout(Duration::from_secs(1), server)
+            .await
+            .expect("the server kept writing")
+            .unwrap();
+    }
+}
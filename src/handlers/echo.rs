@@ -1,23 +1,17 @@
-use std::collections::HashMap;
+use crate::router::extract::{Fields, FromFields, Path, Rejection};
 
-use crate::{
-    request::HttpRequest,
-    response::{HttpResponse, HttpResponseBuilder},
-    router::HttpError,
-};
-
-pub fn echo(
-    req: HttpRequest,
-    mut params: HashMap<String, String>,
-) -> Result<HttpResponse, HttpError> {
-    let val = params
-        .remove("message")
-        .ok_or(HttpError::new(400, Some("Missing message")))?;
+pub struct EchoParams {
+    message: String,
+}
 
-    let builder = HttpResponseBuilder::default()
-        .status(200, Some("OK"))
-        .encode(req.accepted_encodings)
-        .body(val);
+impl FromFields for EchoParams {
+    fn from_fields(fields: &Fields) -> Result<Self, Rejection> {
+        Ok(EchoParams {
+            message: fields.get("message")?,
+        })
+    }
+}
 
-    Ok(builder.build())
+pub fn echo(Path(params): Path<EchoParams>) -> String {
+    params.message
 }
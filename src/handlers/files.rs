@@ -1,59 +1,80 @@
 use crate::{
-    request::HttpRequest,
-    response::{HttpResponse, HttpResponseBuilder},
-    router::{HttpError, HttpRequestParams},
+    request::{HttpMethod, HttpRequest},
+    response::IntoResponse,
+    router::{
+        extract::{Fields, FromFields, Path, Rejection, State},
+        HttpError, HttpRegexEndpoint, HttpRequestParams, HttpRouter, RouteError, RouterKind,
+    },
+    state::AppState,
 };
-use std::{
-    env, fs,
-    path::{Path, PathBuf},
-};
-
-pub fn get_file(
-    mut _req: HttpRequest,
-    mut params: HttpRequestParams,
-) -> Result<HttpResponse, HttpError> {
+use std::path::{self, Component, PathBuf};
+use tokio::fs;
 
-    let filename = params
-        .remove("filename")
-        .ok_or(HttpError::new(400, Some("Missing filename")))?;
+pub struct FileParams {
+    filename: String,
+}
 
-    let data_dir = env::var("DATA_DIR").unwrap();
-    let file_path = Path::join(&PathBuf::from(data_dir), filename);
+impl FromFields for FileParams {
+    fn from_fields(fields: &Fields) -> Result<Self, Rejection> {
+        Ok(FileParams {
+            filename: fields.get("filename")?,
+        })
+    }
+}
 
-    let contents = fs::read_to_string(file_path)
-        .map_err(|_err| HttpError::new(404, Some("Not Found")))
-        .map(|contents| contents.bytes().collect::<Vec<_>>())?;
+/// Reading and uploading files, relative to wherever the router is mounted.
+pub fn routes(kind: RouterKind, max_upload_size: usize) -> Result<Box<dyn HttpRouter>, RouteError> {
+    kind.build(vec![
+        HttpRegexEndpoint::new_async(HttpMethod::GET, "/:filename", get_file).name("get_file"),
+        HttpRegexEndpoint::new_async(HttpMethod::POST, "/:filename", create_file)
+            .name("create_file")
+            .max_body_size(max_upload_size),
+    ])
+}
 
-    let res = HttpResponseBuilder::default()
-        .status(200, Some("OK"))
-        .header("Content-Type", "application/octet-stream")
-        .body(contents)
-        .build();
+/// Resolves `filename` against the data directory in the application state.
+/// Params arrive percent-decoded, so anything but a plain file name, such as
+/// `..%2Fsecret`, is refused rather than allowed out of the directory.
+fn file_path(state: &AppState, filename: &str) -> Result<PathBuf, HttpError> {
+    let mut components = path::Path::new(filename).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        return Err(HttpError::status(400).body("Invalid filename"));
+    }
 
-    Ok(res)
+    Ok(state.data_dir.join(filename))
 }
 
-pub fn create_file(
-    req: HttpRequest,
-    mut params: HttpRequestParams,
-) -> Result<HttpResponse, HttpError> {
+pub async fn get_file(
+    State(state): State<AppState>,
+    Path(FileParams { filename }): Path<FileParams>,
+) -> Result<Vec<u8>, HttpError> {
+    let file_path = file_path(&state, &filename)?;
 
-    let filename = params
-        .remove("filename")
-        .ok_or(HttpError::new(400, Some("Missing filename")))?;
+    let contents = fs::read(file_path).await?;
 
-    let data_dir = env::var("DATA_DIR").unwrap();
-    let file_path = Path::join(&PathBuf::from(data_dir), filename);
+    Ok(contents)
+}
+
+pub async fn create_file(
+    req: HttpRequest,
+    State(state): State<AppState>,
+    Path(FileParams { filename }): Path<FileParams>,
+) -> Result<impl IntoResponse, HttpError> {
+    let file_path = file_path(&state, &filename)?;
 
     if req.body.is_none() {
         return Err(HttpError::new(422, Some("No body")));
     }
 
-    fs::write(file_path, req.body.unwrap()).map_err(|_err| HttpError::new(404, Some("Not Found")))?;
+    let location = req.url_for(
+        "get_file",
+        &HttpRequestParams::from([("filename".into(), filename)]),
+    )?;
 
-    let res = HttpResponseBuilder::default()
-        .status(201, Some("Created"))
-        .build();
+    fs::write(file_path, req.body.unwrap()).await?;
 
-    Ok(res)
+    Ok((201, [("Location", location)], ()))
 }
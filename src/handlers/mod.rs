@@ -7,6 +7,6 @@ mod query;
 pub use home::home;
 pub use echo::echo;
 pub use user_agent::user_agent;
-pub use files::{get_file, create_file};
+pub use files::routes as file_routes;
 pub use query::query;
 
@@ -1,20 +1,24 @@
-use crate::{
-    request::HttpRequest,
-    response::{HttpResponse, HttpResponseBuilder},
-    router::{HttpError, HttpRequestParams},
-};
+use std::collections::HashMap;
 
-pub fn query(
-    req: HttpRequest,
-    mut params: HttpRequestParams,
-) -> Result<HttpResponse, HttpError> {
-    let query_param = params.remove("query_param").unwrap();
-    let query_value = req.query.unwrap().remove(&query_param).unwrap_or("Missing".into());
+use crate::router::extract::{Fields, FromFields, Path, Query, Rejection};
 
-    let res = HttpResponseBuilder::default()
-        .status(200, Some("OK"))
-        .body(query_value)
-        .build();
+pub struct QueryParams {
+    query_param: String,
+}
 
-    Ok(res)
+impl FromFields for QueryParams {
+    fn from_fields(fields: &Fields) -> Result<Self, Rejection> {
+        Ok(QueryParams {
+            query_param: fields.get("query_param")?,
+        })
+    }
+}
+
+pub fn query(
+    Path(params): Path<QueryParams>,
+    Query(mut query): Query<HashMap<String, String>>,
+) -> String {
+    query
+        .remove(&params.query_param)
+        .unwrap_or("Missing".into())
 }
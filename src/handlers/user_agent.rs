@@ -1,22 +1,17 @@
-use crate::{
-    request::HttpRequest,
-    response::{HttpResponse, HttpResponseBuilder},
-    router::{HttpError, HttpRequestParams},
-};
+use crate::router::extract::{Fields, FromFields, Headers, Rejection};
 
-pub fn user_agent(
-    mut req: HttpRequest,
-    mut _params: HttpRequestParams,
-) -> Result<HttpResponse, HttpError> {
-    let user_agent = req
-        .headers
-        .remove("user-agent")
-        .ok_or(HttpError::new(400, Some("Missing user-agent header")))?;
+pub struct UserAgentHeaders {
+    user_agent: String,
+}
 
-    let res = HttpResponseBuilder::default()
-        .status(200, Some("OK"))
-        .body(user_agent)
-        .build();
+impl FromFields for UserAgentHeaders {
+    fn from_fields(fields: &Fields) -> Result<Self, Rejection> {
+        Ok(UserAgentHeaders {
+            user_agent: fields.get("user-agent")?,
+        })
+    }
+}
 
-    Ok(res)
+pub fn user_agent(Headers(headers): Headers<UserAgentHeaders>) -> String {
+    headers.user_agent
 }
@@ -0,0 +1,323 @@
+use std::{
+    fmt::{self, Display},
+    future::poll_fn,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+};
+
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+pub const DEFAULT_PORT: u16 = 4221;
+
+/// An address the server accepts connections on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    /// Parses `addr` as a TCP address, or a Unix socket path when prefixed
+    /// with `unix:`. TCP addresses without a port, like `0.0.0.0` or `::`,
+    /// listen on `default_port`.
+    pub fn parse(addr: &str, default_port: u16) -> Result<Self, String> {
+        if let Some(path) = addr.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(format!("Missing socket path in {:?}", addr));
+            }
+
+            return Ok(ListenAddr::Unix(path.into()));
+        }
+
+        if let Ok(addr) = SocketAddr::from_str(addr) {
+            return Ok(ListenAddr::Tcp(addr));
+        }
+
+        // Bare IPv6 addresses may come bracketed, as they would with a port.
+        let ip = addr
+            .strip_prefix('[')
+            .and_then(|addr| addr.strip_suffix(']'))
+            .unwrap_or(addr);
+
+        match IpAddr::from_str(ip) {
+            Ok(ip) => Ok(ListenAddr::Tcp(SocketAddr::new(ip, default_port))),
+            Err(_) => Err(format!("Invalid listen address {:?}", addr)),
+        }
+    }
+}
+
+impl Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Removes a socket file left behind by an earlier run, which would make
+/// binding fail. Anything else found at `path` is left alone, as is the
+/// socket of a server that is still listening on it.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> io::Result<()> {
+    use std::os::unix::{fs::FileTypeExt, net};
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists and is not a socket", path.display()),
+        ));
+    }
+
+    if net::UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use by another server", path.display()),
+        ));
+    }
+
+    std::fs::remove_file(path)
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+/// Every socket the server listens on, accepted from as one.
+pub struct Listeners {
+    listeners: Vec<Listener>,
+    /// Where the next accept starts looking, so that a busy listener cannot
+    /// keep the others waiting.
+    next: AtomicUsize,
+}
+
+impl Listeners {
+    pub async fn bind(addrs: &[ListenAddr]) -> io::Result<Self> {
+        let mut listeners = Vec::with_capacity(addrs.len());
+
+        for addr in addrs {
+            let listener = match addr {
+                ListenAddr::Tcp(addr) => Listener::Tcp(TcpListener::bind(addr).await?),
+                #[cfg(unix)]
+                ListenAddr::Unix(path) => {
+                    remove_stale_socket(path)?;
+                    Listener::Unix(UnixListener::bind(path)?, path.clone())
+                }
+                #[cfg(not(unix))]
+                ListenAddr::Unix(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "Unix domain sockets are not supported on this platform",
+                    ))
+                }
+            };
+
+            info!("Listening on {}", addr);
+            listeners.push(listener);
+        }
+
+        Ok(Listeners {
+            listeners,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Accepts the next connection to arrive on any of the listeners, which
+    /// take turns being looked at first.
+    pub async fn accept(self: &Self) -> io::Result<Connection> {
+        poll_fn(|cx| {
+            let start = self.next.load(Ordering::Relaxed);
+
+            for i in 0..self.listeners.len() {
+                let i = (start + i) % self.listeners.len();
+                let accepted = match &self.listeners[i] {
+                    Listener::Tcp(listener) => listener
+                        .poll_accept(cx)
+                        .map_ok(|(stream, _)| Connection::Tcp(stream)),
+                    #[cfg(unix)]
+                    Listener::Unix(listener, _) => listener
+                        .poll_accept(cx)
+                        .map_ok(|(stream, _)| Connection::Unix(stream)),
+                };
+
+                if accepted.is_ready() {
+                    self.next.store(i + 1, Ordering::Relaxed);
+                    return accepted;
+                }
+            }
+
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl Drop for Listeners {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        for listener in &self.listeners {
+            if let Listener::Unix(_, path) = listener {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// An accepted connection, whichever kind of socket it came in on.
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(addr: &str) -> Result<String, String> {
+        ListenAddr::parse(addr, 8080).map(|addr| addr.to_string())
+    }
+
+    #[test]
+    fn parses_tcp_addresses() {
+        assert_eq!(parse("127.0.0.1:80"), Ok("127.0.0.1:80".into()));
+        assert_eq!(parse("0.0.0.0"), Ok("0.0.0.0:8080".into()));
+        assert_eq!(parse("[::1]:80"), Ok("[::1]:80".into()));
+    }
+
+    #[test]
+    fn parses_ipv6_addresses_with_or_without_brackets() {
+        assert_eq!(parse("::"), Ok("[::]:8080".into()));
+        assert_eq!(parse("[::]"), Ok("[::]:8080".into()));
+        assert_eq!(parse("fe80::1"), Ok("[fe80::1]:8080".into()));
+        assert_eq!(parse("[fe80::1]"), Ok("[fe80::1]:8080".into()));
+    }
+
+    #[test]
+    fn parses_unix_socket_paths() {
+        assert_eq!(
+            ListenAddr::parse("unix:/run/http.sock", 8080),
+            Ok(ListenAddr::Unix("/run/http.sock".into()))
+        );
+        assert_eq!(
+            ListenAddr::parse("unix:relative.sock", 8080),
+            Ok(ListenAddr::Unix("relative.sock".into()))
+        );
+        assert_eq!(
+            parse("unix:"),
+            Err("Missing socket path in \"unix:\"".into())
+        );
+    }
+
+    #[test]
+    fn rejects_other_addresses() {
+        for addr in [
+            "",
+            "localhost",
+            "localhost:80",
+            "1.2.3.4:",
+            "[::1",
+            "::1]",
+            "1.2.3.4:99999",
+        ] {
+            assert_eq!(
+                parse(addr),
+                Err(format!("Invalid listen address {:?}", addr)),
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn takes_turns_between_listeners() {
+        let dir = std::env::temp_dir();
+        let paths = ["a", "b"].map(|name| {
+            dir.join(format!(
+                "listener-test-{}-{}.sock",
+                std::process::id(),
+                name
+            ))
+        });
+        let listeners = Listeners::bind(&paths.clone().map(ListenAddr::Unix))
+            .await
+            .unwrap();
+
+        let _clients = [&paths[0], &paths[0], &paths[0], &paths[1]]
+            .map(|path| std::os::unix::net::UnixStream::connect(path).unwrap());
+
+        let mut accepted = vec![];
+        for _ in 0..4 {
+            let Connection::Unix(stream) = listeners.accept().await.unwrap() else {
+                panic!("expected a Unix socket");
+            };
+            let addr = stream.local_addr().unwrap();
+            accepted.push(addr.as_pathname().unwrap().to_path_buf());
+        }
+
+        let [a, b] = paths;
+        assert_eq!(accepted, [a.clone(), b, a.clone(), a]);
+    }
+}
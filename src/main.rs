@@ -1,76 +1,230 @@
+#![allow(clippy::needless_arbitrary_self_type, clippy::upper_case_acronyms)]
+
+#[macro_use]
+mod log;
+
+mod config;
+mod connection;
 mod handlers;
+mod listener;
+mod middleware;
+mod parser;
+mod pool;
 mod request;
 mod response;
 mod router;
 mod shared;
+mod shutdown;
+mod state;
 
-use crate::{request::HttpRequestReader, response::HttpResponseWriter};
-use handlers::{create_file, echo, get_file, home, query, user_agent};
+use config::{Command, Config, Site};
+use connection::{reject_connection, serve_connection};
+use handlers::{echo, file_routes, home, query, user_agent};
+use listener::Listeners;
+use middleware::{Compression, Logger, Pipeline, Timing};
+use pool::{ConnectionLimiter, QueueFullPolicy};
 use request::HttpMethod;
-use router::{HttpRegexEndpoint, HttpRouter, RegexRouter};
-use std::env::Args;
+use router::{
+    ConfigureHandlers, HttpRegexEndpoint, HttpRouter, PrefixRouter, RouteError, VirtualHostRouter,
+};
+use state::AppState;
 use std::env;
-use std::net::TcpListener;
-use std::{io::Write, net::TcpStream, thread};
-
-pub fn dispatch(router: impl HttpRouter, mut stream: TcpStream) {
-    let req = stream.read_http_req();
-    if req.is_err() {
-        println!(
-            "Received invalid request: {:?}",
-            &req.err().unwrap().message()
-        );
-
-        return;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{task::JoinSet, time};
+
+/// How long to wait before accepting again after failing to.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+fn main() {
+    let program = env::args().next().unwrap_or("http-server".into());
+
+    let config = match config::load(env::args().skip(1)) {
+        Ok(Command::Serve(config)) => *config,
+        Ok(Command::Help) => {
+            print!("{}", config::usage(&program));
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("{}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\nRun {} --help for usage.", err, program);
+            process::exit(2);
+        }
+    };
+
+    log::set_level(config.log_level);
+    println!("Logs from your program will appear here!");
+
+    let router = match sites(&config) {
+        Ok(router) => router,
+        Err(err) => {
+            eprintln!("error: invalid routes: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let mut router = Pipeline::new(router).layer(Logger).layer(Timing);
+
+    if config.compression {
+        router = router.layer(Compression);
     }
 
-    let req = req.unwrap();
-    let res = router.dispatch(req);
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.pool.workers)
+        .enable_all()
+        .build()
+        .unwrap();
 
-    let _ = stream.write_http_res(res);
-    let _ = stream.flush();
+    runtime.block_on(serve(Arc::new(router), config));
 }
 
-fn main() {
-    println!("Logs from your program will appear here!");
+/// The default site, along with one more for each configured host, all
+/// with the same routes but each with files from its own directory.
+fn sites(config: &Config) -> Result<VirtualHostRouter<PrefixRouter>, RouteError> {
+    let site = |data_dir: &Path| -> Result<PrefixRouter, RouteError> {
+        let state = AppState {
+            data_dir: data_dir.to_path_buf(),
+        };
 
-    let data_dir = parse_directory_flag(env::args()).unwrap_or(env!("PWD").to_string());
-    env::set_var("DATA_DIR", data_dir);
-
-    let router = RegexRouter {
-        endpoints: vec![
-            HttpRegexEndpoint::new(HttpMethod::GET, "/", home),
-            HttpRegexEndpoint::new(HttpMethod::GET, "/echo/:message", echo),
-            HttpRegexEndpoint::new(HttpMethod::GET, "/user-agent", user_agent),
-            HttpRegexEndpoint::new(HttpMethod::GET, "/files/:filename", get_file),
-            HttpRegexEndpoint::new(HttpMethod::POST, "/files/:filename", create_file),
-            HttpRegexEndpoint::new(HttpMethod::GET, "/query/:query_param", query),
-        ],
+        Ok(routes(config)?
+            .with_state(state)
+            .with_error_renderer(config.error_format))
     };
 
-    let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
+    let mut router = VirtualHostRouter::new(site(&config.data_dir)?);
+    for Site { host, data_dir } in &config.sites {
+        router = router.host(host, site(data_dir)?);
+    }
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
+    Ok(router)
+}
+
+fn routes(config: &Config) -> Result<PrefixRouter, RouteError> {
+    let root = config.router.build(vec![
+        HttpRegexEndpoint::new(HttpMethod::GET, "/", home).name("home"),
+        HttpRegexEndpoint::new(HttpMethod::GET, "/echo/:message", echo).name("echo"),
+        HttpRegexEndpoint::new(HttpMethod::GET, "/user-agent", user_agent).name("user_agent"),
+        HttpRegexEndpoint::new(HttpMethod::GET, "/query/:query_param", query).name("query"),
+    ])?;
+
+    PrefixRouter::new(root).mount(
+        "/files",
+        file_routes(config.router, config.max_upload_size)?,
+    )
+}
+
+async fn serve(router: Arc<impl HttpRouter + 'static>, config: Config) {
+    let limiter = ConnectionLimiter::new(&config.pool);
+    let listeners = match Listeners::bind(&config.listen).await {
+        Ok(listeners) => listeners,
+        Err(err) => {
+            error!("Failed to bind listener: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let (shutdown_trigger, shutdown) = shutdown::channel();
+    let mut connections = JoinSet::new();
+    let signal = shutdown::wait_for_signal();
+    tokio::pin!(signal);
+
+    loop {
+        let stream = tokio::select! {
+            accepted = listeners.accept() => match accepted {
+                Ok(stream) => stream,
+                Err(e) => {
+                    // Errors like running out of file descriptors last a
+                    // while, so retrying right away would only spin.
+                    error!("{}", e);
+                    time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            // Reap finished connections so their results do not pile up.
+            Some(_) = connections.join_next() => continue,
+            _ = &mut signal => break,
+        };
+
+        if limiter.is_full() && config.pool.queue_full_policy == QueueFullPolicy::Block {
+            let metrics = limiter.metrics();
+            warn!(
+                "Server full ({} active, {} already waiting), holding connection",
+                metrics.active_connections, metrics.queue_depth
+            );
+        }
+
+        // A full server must not hold up shutdown while it waits for a slot.
+        let admitted = tokio::select! {
+            admitted = limiter.admit() => admitted,
+            _ = &mut signal => break,
+        };
+
+        let timeouts = config.timeouts.clone();
+        match admitted {
+            Ok(admission) => {
                 let router = router.clone();
-                let _ = thread::spawn(|| dispatch(router, stream));
+                let shutdown = shutdown.clone();
+                connections.spawn(async move {
+                    // Connections still queued at shutdown are closed unanswered.
+                    let mut waiting = shutdown.clone();
+                    let permit = tokio::select! {
+                        permit = admission.permit() => permit,
+                        _ = waiting.triggered() => return,
+                    };
+
+                    serve_connection(
+                        router.as_ref(),
+                        stream,
+                        &timeouts,
+                        config.max_body_size,
+                        shutdown,
+                    )
+                    .await;
+                    drop(permit);
+                });
             }
-            Err(e) => {
-                println!("error: {}", e);
+            Err(retry_after) => {
+                let metrics = limiter.metrics();
+                warn!(
+                    "Server full ({} active, peak queue {}), rejecting connection #{}",
+                    metrics.active_connections, metrics.max_queue_depth, metrics.rejected
+                );
+
+                connections.spawn(async move {
+                    reject_connection(stream, retry_after, &timeouts).await;
+                });
             }
         }
     }
-}
 
+    // Stop accepting, close idle connections and let in-flight requests
+    // finish, up to the grace period.
+    drop(listeners);
+    shutdown_trigger.trigger();
+    info!(
+        "Shutting down, waiting up to {}s for {} connection(s)",
+        config.grace_period.as_secs(),
+        connections.len()
+    );
 
-fn parse_directory_flag(mut argv: Args) -> Option<String> {
-    while let Some(arg) = argv.next() {
-        if arg == "--directory" {
-            return argv.next();
-        }
-    };
+    let drained = time::timeout(config.grace_period, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
 
-    None
+    match drained {
+        Ok(()) => info!("Shut down gracefully"),
+        Err(_) => {
+            warn!(
+                "Grace period elapsed, dropping {} connection(s) and shutting down",
+                connections.len()
+            );
+            connections.shutdown().await;
+        }
+    }
 }
-
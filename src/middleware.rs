@@ -0,0 +1,303 @@
+use std::{sync::Arc, time::Instant};
+
+use crate::{
+    request::HttpRequest,
+    response::{gzip_encode, HttpResponse},
+    router::{BoxFuture, HttpError, HttpRouter, RouteNames},
+    shared::HttpEncodingScheme,
+};
+
+/// Logic that runs around every request a `Pipeline` dispatches.
+///
+/// A middleware passes the request on by calling `next.run(req)`, and can
+/// change the request beforehand or the response afterwards. Returning a
+/// response without calling `next` short-circuits the rest of the pipeline,
+/// router included.
+///
+/// Requests turned down before their body is read go through `handle` too,
+/// with `next` answering them with the router's rendering of the error.
+pub trait Middleware: Send + Sync {
+    fn handle<'a>(self: &'a Self, req: HttpRequest, next: Next<'a>) -> BoxFuture<'a, HttpResponse>;
+
+    /// Decides whether a request may go ahead before its body is read, e.g.
+    /// to turn down unauthenticated uploads without receiving them. Runs
+    /// before the router's own check; the request has no body yet.
+    fn precheck(self: &Self, _req: &HttpRequest) -> Result<(), HttpError> {
+        Ok(())
+    }
+}
+
+/// What the pipeline ends in: the router, or the rejection of a request
+/// that never reached it.
+enum Endpoint<'a> {
+    Router(&'a dyn HttpRouter),
+    Rejected(&'a dyn HttpRouter, HttpError),
+}
+
+/// The rest of the pipeline after the current middleware.
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    endpoint: Endpoint<'a>,
+}
+
+impl<'a> Next<'a> {
+    pub fn run(self: Self, req: HttpRequest) -> BoxFuture<'a, HttpResponse> {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                req,
+                Next {
+                    middleware: rest,
+                    endpoint: self.endpoint,
+                },
+            ),
+            None => match self.endpoint {
+                Endpoint::Router(router) => router.dispatch(req),
+                Endpoint::Rejected(router, err) => router.reject(req, err),
+            },
+        }
+    }
+}
+
+/// Wraps a router in layers of middleware. The first layer added is the
+/// outermost one: it sees requests first and responses last.
+pub struct Pipeline<R: HttpRouter> {
+    middleware: Vec<Arc<dyn Middleware>>,
+    router: R,
+}
+
+impl<R: HttpRouter> Pipeline<R> {
+    pub fn new(router: R) -> Self {
+        Pipeline {
+            middleware: vec![],
+            router,
+        }
+    }
+
+    pub fn layer(mut self: Self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+
+        self
+    }
+}
+
+impl<R: HttpRouter> HttpRouter for Pipeline<R> {
+    fn dispatch(self: &Self, req: HttpRequest) -> BoxFuture<'_, HttpResponse> {
+        let next = Next {
+            middleware: &self.middleware,
+            endpoint: Endpoint::Router(&self.router),
+        };
+
+        next.run(req)
+    }
+
+    fn precheck(self: &Self, req: &HttpRequest) -> Result<Option<usize>, HttpError> {
+        for middleware in &self.middleware {
+            middleware.precheck(req)?;
+        }
+
+        self.router.precheck(req)
+    }
+
+    /// Rejections pass through every layer, so they are logged, timed and
+    /// compressed like any other response.
+    fn reject(self: &Self, req: HttpRequest, err: HttpError) -> BoxFuture<'_, HttpResponse> {
+        let next = Next {
+            middleware: &self.middleware,
+            endpoint: Endpoint::Rejected(&self.router, err),
+        };
+
+        next.run(req)
+    }
+
+    fn route_names(self: &Self) -> Arc<RouteNames> {
+        self.router.route_names()
+    }
+}
+
+/// Logs each request with the status it was answered with and how long that
+/// took.
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle<'a>(self: &'a Self, req: HttpRequest, next: Next<'a>) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let started = Instant::now();
+            let method = req.method.clone();
+            let path = req.target.path().to_string();
+
+            let res = next.run(req).await;
+
+            info!(
+                "{} {} {} ({:.1?})",
+                method,
+                path,
+                res.status_code,
+                started.elapsed()
+            );
+
+            res
+        })
+    }
+}
+
+/// Reports how long the rest of the pipeline took to produce a response in
+/// a `Server-Timing` header.
+pub struct Timing;
+
+impl Middleware for Timing {
+    fn handle<'a>(self: &'a Self, req: HttpRequest, next: Next<'a>) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let started = Instant::now();
+            let mut res = next.run(req).await;
+
+            let elapsed = started.elapsed().as_secs_f64() * 1000.0;
+            res.headers
+                .insert("server-timing".into(), format!("app;dur={:.3}", elapsed));
+
+            res
+        })
+    }
+}
+
+/// Gzip-encodes response bodies for clients that accept it.
+pub struct Compression;
+
+impl Middleware for Compression {
+    fn handle<'a>(self: &'a Self, req: HttpRequest, next: Next<'a>) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let accepts_gzip = req.accepted_encodings.contains(&HttpEncodingScheme::Gzip);
+            let mut res = next.run(req).await;
+
+            res.headers.insert("vary".into(), "accept-encoding".into());
+
+            // Responses a handler already encoded are left as they are.
+            if !accepts_gzip || res.headers.contains_key("content-encoding") {
+                return res;
+            }
+
+            if let Some(body) = res.body.take() {
+                let (content_length, body) = gzip_encode(body.len(), body);
+
+                res.headers.insert("content-encoding".into(), "gzip".into());
+                res.headers
+                    .insert("content-length".into(), content_length.to_string());
+                res.body = Some(body);
+            }
+
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        request::HttpMethod,
+        router::{
+            testing::request, ConfigureHandlers, ErrorFormat, HttpRegexEndpoint, RegexRouter,
+        },
+    };
+
+    use super::*;
+
+    /// Turns down requests without credentials before reading their body.
+    struct RequireAuth;
+
+    impl Middleware for RequireAuth {
+        fn handle<'a>(
+            self: &'a Self,
+            req: HttpRequest,
+            next: Next<'a>,
+        ) -> BoxFuture<'a, HttpResponse> {
+            next.run(req)
+        }
+
+        fn precheck(self: &Self, req: &HttpRequest) -> Result<(), HttpError> {
+            match req.headers.get("authorization") {
+                Some(_) => Ok(()),
+                None => Err(HttpError::status(401).header("WWW-Authenticate", "Bearer")),
+            }
+        }
+    }
+
+    fn pipeline() -> Pipeline<RegexRouter> {
+        let router = RegexRouter::new(vec![HttpRegexEndpoint::new(
+            HttpMethod::POST,
+            "/upload",
+            || "uploaded",
+        )
+        .max_body_size(16)])
+        .unwrap()
+        .with_error_renderer(ErrorFormat::Json);
+
+        Pipeline::new(router).layer(Timing).layer(RequireAuth)
+    }
+
+    fn authorized(method: &str, path: &str) -> HttpRequest {
+        let mut req = request(method, path);
+        req.headers.insert("authorization", "Bearer token");
+
+        req
+    }
+
+    /// Runs the pre-body check and answers the request the way a connection
+    /// would if it fails.
+    async fn precheck(router: &impl HttpRouter, req: HttpRequest) -> Option<HttpResponse> {
+        match router.precheck(&req) {
+            Ok(_) => None,
+            Err(err) => Some(router.reject(req, err).await),
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_rejects_requests_before_their_body() {
+        let res = precheck(&pipeline(), request("POST", "/upload"))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status_code, 401);
+        assert_eq!(res.headers.get("www-authenticate").unwrap(), "Bearer");
+    }
+
+    #[tokio::test]
+    async fn middleware_checks_run_before_the_router() {
+        let res = precheck(&pipeline(), request("POST", "/missing"))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status_code, 401);
+    }
+
+    #[tokio::test]
+    async fn rejections_pass_through_every_layer() {
+        let res = precheck(&pipeline(), authorized("POST", "/missing"))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status_code, 404);
+        assert!(res.headers.contains_key("server-timing"));
+    }
+
+    #[tokio::test]
+    async fn rejections_use_the_error_renderer() {
+        let mut req = authorized("POST", "/upload");
+        req.headers.insert("content-length", "17");
+
+        let res = precheck(&pipeline(), req).await.unwrap();
+
+        assert_eq!(res.status_code, 413);
+        assert_eq!(
+            res.headers.get("content-type").unwrap(),
+            "application/problem+json"
+        );
+    }
+
+    #[tokio::test]
+    async fn accepted_requests_get_their_endpoint_limit() {
+        let router = pipeline();
+        let req = authorized("POST", "/upload");
+
+        assert_eq!(router.precheck(&req).unwrap(), Some(16));
+        assert_eq!(router.dispatch(req).await.status_code, 200);
+    }
+}
@@ -0,0 +1,538 @@
+//! Incremental HTTP/1.1 message parsers built on `nom`.
+//!
+//! Every parser here works on a byte buffer that may hold only part of a
+//! message: when more input is required they return `nom::Err::Incomplete`
+//! so that callers can read more bytes from the connection and retry. They
+//! perform no I/O themselves and can be driven from any transport.
+
+use nom::{
+    bytes::streaming::{tag, take, take_while, take_while1, take_while_m_n},
+    character::streaming::{char, crlf, hex_digit1},
+    combinator::{cut, map, map_res, not, opt},
+    error::{context, VerboseError},
+    multi::many0,
+    sequence::{preceded, separated_pair, terminated, tuple},
+    IResult,
+};
+
+pub type ParseResult<'a, T> = IResult<&'a [u8], T, VerboseError<&'a [u8]>>;
+
+/// The four forms a request target can take (RFC 9112, section 3.2).
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestTarget<'a> {
+    Origin {
+        path: &'a str,
+        query: Option<&'a str>,
+    },
+    Absolute {
+        scheme: &'a str,
+        authority: &'a str,
+        path: &'a str,
+        query: Option<&'a str>,
+    },
+    Authority(&'a str),
+    Asterisk,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RequestLine<'a> {
+    pub method: &'a str,
+    pub target: RequestTarget<'a>,
+    pub version: (u8, u8),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RequestHead<'a> {
+    pub request_line: RequestLine<'a>,
+    pub headers: Vec<(&'a str, &'a str)>,
+}
+
+fn is_tchar(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+fn is_ows(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+fn is_field_vchar(c: u8) -> bool {
+    c == b'\t' || (c >= 0x20 && c != 0x7f)
+}
+
+fn is_target_char(c: u8) -> bool {
+    c.is_ascii_graphic()
+}
+
+fn utf8(input: &[u8]) -> Result<&str, std::str::Utf8Error> {
+    std::str::from_utf8(input)
+}
+
+fn token(input: &[u8]) -> ParseResult<'_, &str> {
+    map_res(take_while1(is_tchar), utf8)(input)
+}
+
+fn http_version(input: &[u8]) -> ParseResult<'_, (u8, u8)> {
+    let digit = |input| {
+        map(
+            take_while_m_n(1, 1, |c: u8| c.is_ascii_digit()),
+            |digit: &[u8]| digit[0] - b'0',
+        )(input)
+    };
+
+    preceded(tag("HTTP/"), separated_pair(digit, char('.'), digit))(input)
+}
+
+/// `request-line = method SP request-target SP HTTP-version CRLF`
+pub fn request_line(input: &[u8]) -> ParseResult<'_, RequestLine<'_>> {
+    map(
+        tuple((
+            context("method", token),
+            char(' '),
+            context(
+                "request target",
+                map_res(map_res(take_while1(is_target_char), utf8), |target| {
+                    request_target(target).map(|(_, target)| target)
+                }),
+            ),
+            char(' '),
+            context("HTTP version", http_version),
+            context("request line", crlf),
+        )),
+        |(method, _, target, _, version, _)| RequestLine {
+            method,
+            target,
+            version,
+        },
+    )(input)
+}
+
+/// `field-line = field-name ":" OWS field-value OWS CRLF`
+///
+/// Lines starting with whitespace (obsolete line folding) and whitespace
+/// between the field name and the colon are rejected outright, since both
+/// are classic request smuggling vectors.
+pub fn header_field(input: &[u8]) -> ParseResult<'_, (&str, &str)> {
+    let (input, _) = context("obsolete line folding", cut(not(take_while1(is_ows))))(input)?;
+
+    let (input, name) = token(input)?;
+    let (input, value) = context(
+        "header field",
+        cut(preceded(
+            tuple((char(':'), take_while(is_ows))),
+            terminated(map_res(take_while(is_field_vchar), utf8), crlf),
+        )),
+    )(input)?;
+
+    Ok((input, (name, value.trim_end_matches([' ', '\t']))))
+}
+
+/// Parses the request line and header section up to and including the
+/// empty line that terminates it.
+pub fn request_head(input: &[u8]) -> ParseResult<'_, RequestHead<'_>> {
+    map(
+        tuple((
+            request_line,
+            many0(header_field),
+            context("end of header section", crlf),
+        )),
+        |(request_line, headers, _)| RequestHead {
+            request_line,
+            headers,
+        },
+    )(input)
+}
+
+/// `chunk-size [ chunk-ext ] CRLF`, ignoring any chunk extensions.
+pub fn chunk_size(input: &[u8]) -> ParseResult<'_, usize> {
+    context(
+        "chunk size",
+        terminated(
+            map_res(map_res(hex_digit1, utf8), |digits| {
+                usize::from_str_radix(digits, 16)
+            }),
+            tuple((opt(preceded(char(';'), take_while(is_field_vchar))), crlf)),
+        ),
+    )(input)
+}
+
+/// One piece of a `chunked` transfer coding.
+#[derive(Debug, PartialEq)]
+pub enum Chunk<'a> {
+    Data(&'a [u8]),
+    /// The last chunk, along with any trailer fields and the final CRLF.
+    Last(Vec<(&'a str, &'a str)>),
+}
+
+/// Parses the next chunk of a `chunked` transfer coding, so that callers can
+/// consume chunks as they complete instead of waiting for the whole body.
+pub fn chunk(input: &[u8]) -> ParseResult<'_, Chunk<'_>> {
+    let (rest, size) = chunk_size(input)?;
+    if size == 0 {
+        let (rest, trailers) = many0(header_field)(rest)?;
+        let (rest, _) = context("end of chunked body", crlf)(rest)?;
+
+        return Ok((rest, Chunk::Last(trailers)));
+    }
+
+    let (rest, data) = context("chunk data", terminated(take(size), crlf))(rest)?;
+
+    Ok((rest, Chunk::Data(data)))
+}
+
+/// `request-target = origin-form / absolute-form / authority-form / asterisk-form`
+///
+/// Operates on a complete target, as delimited by the request line.
+pub fn request_target(input: &str) -> IResult<&str, RequestTarget<'_>> {
+    use nom::{
+        branch::alt,
+        bytes::complete::{tag, take_while, take_while1},
+        character::complete::{alpha1, char},
+        combinator::{all_consuming, recognize, rest, value, verify},
+        sequence::pair,
+    };
+
+    let query = |input| opt(preceded(char('?'), rest))(input);
+    let origin = map(
+        pair(recognize(pair(char('/'), take_while(|c| c != '?'))), query),
+        |(path, query)| RequestTarget::Origin { path, query },
+    );
+
+    let absolute = map(
+        tuple((
+            recognize(pair(
+                alpha1,
+                take_while(|c: char| c.is_ascii_alphanumeric() || "+-.".contains(c)),
+            )),
+            tag("://"),
+            take_while1(|c| c != '/' && c != '?'),
+            take_while(|c| c != '?'),
+            query,
+        )),
+        |(scheme, _, authority, path, query)| RequestTarget::Absolute {
+            scheme,
+            authority,
+            path: if path.is_empty() { "/" } else { path },
+            query,
+        },
+    );
+
+    let authority = map(
+        verify(take_while1(|c| c != '/' && c != '?'), |authority: &str| {
+            authority.contains(':')
+        }),
+        RequestTarget::Authority,
+    );
+
+    all_consuming(alt((
+        value(RequestTarget::Asterisk, tag("*")),
+        origin,
+        absolute,
+        authority,
+    )))(input)
+}
+
+/// `query = key "=" value *( "&" key "=" value )`
+///
+/// Unlike the message parsers above this operates on a complete string.
+pub fn query_string(input: &str) -> IResult<&str, Vec<(&str, &str)>> {
+    use nom::{
+        bytes::complete::take_till, character::complete::char, combinator::all_consuming,
+        multi::separated_list0,
+    };
+
+    all_consuming(separated_list0(
+        char('&'),
+        separated_pair(
+            take_till(|c| c == '=' || c == '&'),
+            char('='),
+            take_till(|c| c == '&'),
+        ),
+    ))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a request with the given header lines, returning its headers,
+    /// or `None` if the head is rejected.
+    fn headers(fields: &str) -> Option<Vec<(String, String)>> {
+        let input = format!("GET / HTTP/1.1\r\n{}\r\n", fields);
+
+        match request_head(input.as_bytes()) {
+            Ok((rest, head)) => {
+                assert!(rest.is_empty());
+
+                let headers = head
+                    .headers
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect();
+
+                Some(headers)
+            }
+            Err(nom::Err::Incomplete(_)) => panic!("head reported incomplete: {:?}", input),
+            Err(_) => None,
+        }
+    }
+
+    fn header(name: &str, value: &str) -> Vec<(String, String)> {
+        vec![(name.to_string(), value.to_string())]
+    }
+
+    #[test]
+    fn parses_request_line() {
+        let (rest, line) = request_line(b"GET /files/a?x=1 HTTP/1.1\r\n").unwrap();
+
+        assert!(rest.is_empty());
+        assert_eq!(
+            line,
+            RequestLine {
+                method: "GET",
+                target: RequestTarget::Origin {
+                    path: "/files/a",
+                    query: Some("x=1"),
+                },
+                version: (1, 1),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_each_request_target_form() {
+        let target = |input| request_target(input).map(|(_, target)| target).ok();
+
+        assert_eq!(
+            target("/a/b?c=d?e"),
+            Some(RequestTarget::Origin {
+                path: "/a/b",
+                query: Some("c=d?e"),
+            })
+        );
+        assert_eq!(
+            target("http://user@example.com:8080/a?b"),
+            Some(RequestTarget::Absolute {
+                scheme: "http",
+                authority: "user@example.com:8080",
+                path: "/a",
+                query: Some("b"),
+            })
+        );
+        assert_eq!(
+            target("https://example.com?b"),
+            Some(RequestTarget::Absolute {
+                scheme: "https",
+                authority: "example.com",
+                path: "/",
+                query: Some("b"),
+            })
+        );
+        assert_eq!(
+            target("example.com:443"),
+            Some(RequestTarget::Authority("example.com:443"))
+        );
+        assert_eq!(
+            target("[::1]:443"),
+            Some(RequestTarget::Authority("[::1]:443"))
+        );
+        assert_eq!(target("*"), Some(RequestTarget::Asterisk));
+    }
+
+    #[test]
+    fn rejects_other_request_targets() {
+        for input in [
+            "",
+            "example.com",
+            "**",
+            "*/a",
+            "http://",
+            "1http://a/",
+            "a b",
+        ] {
+            assert!(request_target(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn accepts_value_without_leading_space() {
+        assert_eq!(headers("Host:x\r\n"), Some(header("Host", "x")));
+    }
+
+    #[test]
+    fn accepts_tab_as_whitespace() {
+        assert_eq!(headers("Host:\tx\r\n"), Some(header("Host", "x")));
+        assert_eq!(
+            headers("Accept: a,\tb\r\n"),
+            Some(header("Accept", "a,\tb"))
+        );
+    }
+
+    #[test]
+    fn trims_trailing_whitespace() {
+        assert_eq!(headers("Host: x \t \r\n"), Some(header("Host", "x")));
+    }
+
+    #[test]
+    fn keeps_header_order_and_duplicates() {
+        assert_eq!(
+            headers("Content-Length: 1\r\nContent-Length: 2\r\n"),
+            Some(vec![
+                ("Content-Length".to_string(), "1".to_string()),
+                ("Content-Length".to_string(), "2".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_whitespace_before_colon() {
+        assert_eq!(headers("Host : x\r\n"), None);
+        assert_eq!(headers("Content-Length\t: 5\r\n"), None);
+    }
+
+    #[test]
+    fn rejects_obsolete_line_folding() {
+        assert_eq!(headers("X-Long: a\r\n b\r\n"), None);
+        assert_eq!(headers("X-Long: a\r\n\tb\r\n"), None);
+        assert_eq!(headers(" Host: x\r\n"), None);
+    }
+
+    #[test]
+    fn rejects_non_token_names() {
+        assert_eq!(headers("Ho(st: x\r\n"), None);
+        assert_eq!(headers("Ho st: x\r\n"), None);
+        assert_eq!(headers("\"Host\": x\r\n"), None);
+        assert_eq!(headers(": x\r\n"), None);
+    }
+
+    #[test]
+    fn rejects_control_characters_in_values() {
+        assert_eq!(headers("X-A: a\rb\r\n"), None);
+        assert_eq!(headers("X-A: a\nb\r\n"), None);
+        assert_eq!(headers("X-A: a\0b\r\n"), None);
+    }
+
+    #[test]
+    fn rejects_bare_lf_line_endings() {
+        assert_eq!(headers("Host: x\n"), None);
+        assert!(request_head(b"GET / HTTP/1.1\nHost: x\r\n\r\n").is_err());
+    }
+
+    type Trailers<'a> = Vec<(&'a str, &'a str)>;
+
+    /// Decodes a whole chunked body, the way the connection does it a chunk
+    /// at a time, returning the payload, trailers and unconsumed input.
+    fn dechunk(mut input: &[u8]) -> ParseResult<'_, (Vec<u8>, Trailers<'_>)> {
+        let mut body = Vec::new();
+
+        loop {
+            match chunk(input)? {
+                (rest, Chunk::Data(data)) => {
+                    body.extend_from_slice(data);
+                    input = rest;
+                }
+                (rest, Chunk::Last(trailers)) => return Ok((rest, (body, trailers))),
+            }
+        }
+    }
+
+    /// Whether a parse succeeded, needed more input or failed outright.
+    #[derive(Debug, PartialEq)]
+    enum Outcome {
+        Done,
+        Incomplete,
+        Failed,
+    }
+
+    fn outcome<T>(result: ParseResult<'_, T>) -> Outcome {
+        match result {
+            Ok(_) => Outcome::Done,
+            Err(nom::Err::Incomplete(_)) => Outcome::Incomplete,
+            Err(_) => Outcome::Failed,
+        }
+    }
+
+    /// Asserts that every proper prefix of `input` is reported incomplete,
+    /// as it would be when it trickles in one byte at a time.
+    fn assert_incomplete_until_complete(input: &[u8], parse: impl Fn(&[u8]) -> Outcome) {
+        for end in 0..input.len() {
+            assert_eq!(
+                parse(&input[..end]),
+                Outcome::Incomplete,
+                "{:?}",
+                String::from_utf8_lossy(&input[..end])
+            );
+        }
+
+        assert_eq!(parse(input), Outcome::Done);
+    }
+
+    #[test]
+    fn request_head_is_incomplete_until_the_empty_line() {
+        let input = b"POST /files/a?x=1 HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\n";
+
+        assert_incomplete_until_complete(input, |input| outcome(request_head(input)));
+    }
+
+    #[test]
+    fn request_head_leaves_the_body_unconsumed() {
+        let (rest, head) = request_head(b"GET / HTTP/1.1\r\nHost: x\r\n\r\nhello").unwrap();
+
+        assert_eq!(rest, b"hello");
+        assert_eq!(head.headers, vec![("Host", "x")]);
+    }
+
+    #[test]
+    fn chunked_body_is_incomplete_until_the_last_chunk() {
+        let input = b"5\r\nhello\r\n1;ext=1\r\n!\r\n0\r\nX-Sum: 1\r\n\r\n";
+
+        assert_incomplete_until_complete(input, |input| outcome(dechunk(input)));
+    }
+
+    #[test]
+    fn decodes_chunked_body() {
+        let (rest, (body, trailers)) =
+            dechunk(b"5\r\nhello\r\nA\r\n, world!!!\r\n0\r\n\r\nGET").unwrap();
+
+        assert_eq!(body, b"hello, world!!!");
+        assert!(trailers.is_empty());
+        assert_eq!(rest, b"GET");
+    }
+
+    #[test]
+    fn parses_trailers() {
+        let (rest, (body, trailers)) =
+            dechunk(b"3\r\nabc\r\n0\r\nX-Sum: 1\r\nX-Other:two \r\n\r\n").unwrap();
+
+        assert_eq!(body, b"abc");
+        assert_eq!(trailers, vec![("X-Sum", "1"), ("X-Other", "two")]);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_trailers() {
+        assert!(dechunk(b"0\r\nX-Sum : 1\r\n\r\n").is_err());
+        assert!(dechunk(b"0\r\nX-Sum: 1\r\n folded\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn rejects_chunk_size_overflow() {
+        let result = chunk(b"10000000000000000\r\n");
+
+        assert!(matches!(
+            result,
+            Err(nom::Err::Error(_) | nom::Err::Failure(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        // Data longer than the declared size.
+        assert!(dechunk(b"3\r\nabcd\r\n0\r\n\r\n").is_err());
+        // Sizes must be hex digits, without a sign or whitespace.
+        assert!(dechunk(b"-3\r\nabc\r\n0\r\n\r\n").is_err());
+        assert!(dechunk(b" 3\r\nabc\r\n0\r\n\r\n").is_err());
+        assert!(dechunk(b"x\r\n0\r\n\r\n").is_err());
+        // Chunk data must end in CRLF.
+        assert!(dechunk(b"3\r\nabc\n0\r\n\r\n").is_err());
+    }
+}
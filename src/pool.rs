@@ -0,0 +1,279 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// What to do with a new connection when the server is already serving as
+/// many as it is allowed to and the queue of connections waiting for a slot
+/// is full too.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueueFullPolicy {
+    /// Hold the connection, and stop accepting, until a slot frees up.
+    Block,
+    /// Turn the connection away, asking clients to retry after the given delay.
+    Reject { retry_after: Duration },
+}
+
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// Runtime worker threads driving all connections.
+    pub workers: usize,
+    /// Connections, idle keep-alive ones included, served at once.
+    pub max_connections: usize,
+    /// Accepted connections allowed to wait for a slot.
+    pub queue_capacity: usize,
+    pub queue_full_policy: QueueFullPolicy,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            workers: thread::available_parallelism().map_or(4, |n| n.get()),
+            max_connections: 10_000,
+            queue_capacity: 256,
+            queue_full_policy: QueueFullPolicy::Block,
+        }
+    }
+}
+
+/// A snapshot of how loaded the server is.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PoolMetrics {
+    /// Accepted connections waiting for a free slot.
+    pub queue_depth: usize,
+    pub max_queue_depth: usize,
+    pub active_connections: usize,
+    pub rejected: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    queue_depth: AtomicUsize,
+    max_queue_depth: AtomicUsize,
+    active_connections: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+/// Caps how many connections are served at once. Up to `queue_capacity`
+/// more wait for a slot; the rest are held or turned away according to the
+/// configured policy.
+pub struct ConnectionLimiter {
+    slots: Arc<Semaphore>,
+    queue: Arc<Semaphore>,
+    policy: QueueFullPolicy,
+    counters: Arc<Counters>,
+}
+
+/// A connection let in by the limiter, either with a slot already or with
+/// a place in the queue for one.
+pub enum Admission {
+    Ready(ConnectionPermit),
+    Queued(QueuedConnection),
+}
+
+/// A place in the queue, given up once the connection gets its slot.
+pub struct QueuedConnection {
+    _place: OwnedSemaphorePermit,
+    slots: Arc<Semaphore>,
+    counters: Arc<Counters>,
+}
+
+/// A slot held by a connection for as long as it is being served.
+pub struct ConnectionPermit {
+    _permit: OwnedSemaphorePermit,
+    counters: Arc<Counters>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.counters
+            .active_connections
+            .fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ConnectionPermit {
+    fn new(permit: OwnedSemaphorePermit, counters: &Arc<Counters>) -> Self {
+        counters.active_connections.fetch_add(1, Ordering::SeqCst);
+
+        ConnectionPermit {
+            _permit: permit,
+            counters: counters.clone(),
+        }
+    }
+}
+
+impl Drop for QueuedConnection {
+    fn drop(&mut self) {
+        self.counters.queue_depth.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Admission {
+    /// The connection's slot, waiting for one if it was queued. Queued
+    /// connections get their slots in the order they were queued.
+    pub async fn permit(self: Self) -> ConnectionPermit {
+        match self {
+            Admission::Ready(permit) => permit,
+            Admission::Queued(queued) => {
+                let permit = queued
+                    .slots
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("Connection slots are never closed");
+
+                ConnectionPermit::new(permit, &queued.counters)
+            }
+        }
+    }
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: &PoolConfig) -> Self {
+        assert!(
+            config.max_connections > 0,
+            "The server must accept at least one connection"
+        );
+
+        ConnectionLimiter {
+            slots: Arc::new(Semaphore::new(config.max_connections)),
+            queue: Arc::new(Semaphore::new(config.queue_capacity)),
+            policy: config.queue_full_policy.clone(),
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// Lets a newly accepted connection in, with a slot if one is free or
+    /// else with a place in the queue.
+    ///
+    /// When the queue is full too, either waits for a slot, holding up the
+    /// caller, or returns the delay to advertise to the client if the policy
+    /// is to reject.
+    pub async fn admit(self: &Self) -> Result<Admission, Duration> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(Admission::Ready(ConnectionPermit::new(
+                permit,
+                &self.counters,
+            )));
+        }
+
+        if let Ok(place) = self.queue.clone().try_acquire_owned() {
+            let depth = self.counters.queue_depth.fetch_add(1, Ordering::SeqCst) + 1;
+            self.counters
+                .max_queue_depth
+                .fetch_max(depth, Ordering::SeqCst);
+
+            return Ok(Admission::Queued(QueuedConnection {
+                _place: place,
+                slots: self.slots.clone(),
+                counters: self.counters.clone(),
+            }));
+        }
+
+        match &self.policy {
+            // Slots are handed out in order, so this still waits behind
+            // every queued connection.
+            QueueFullPolicy::Block => {
+                let permit = self
+                    .slots
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("Connection slots are never closed");
+
+                Ok(Admission::Ready(ConnectionPermit::new(
+                    permit,
+                    &self.counters,
+                )))
+            }
+            QueueFullPolicy::Reject { retry_after } => {
+                self.counters.rejected.fetch_add(1, Ordering::SeqCst);
+                Err(*retry_after)
+            }
+        }
+    }
+
+    /// Whether new connections can neither be served nor queued right away.
+    pub fn is_full(self: &Self) -> bool {
+        self.slots.available_permits() == 0 && self.queue.available_permits() == 0
+    }
+
+    pub fn metrics(self: &Self) -> PoolMetrics {
+        PoolMetrics {
+            queue_depth: self.counters.queue_depth.load(Ordering::SeqCst),
+            max_queue_depth: self.counters.max_queue_depth.load(Ordering::SeqCst),
+            active_connections: self.counters.active_connections.load(Ordering::SeqCst),
+            rejected: self.counters.rejected.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_connections: usize, queue_capacity: usize) -> ConnectionLimiter {
+        ConnectionLimiter::new(&PoolConfig {
+            workers: 1,
+            max_connections,
+            queue_capacity,
+            queue_full_policy: QueueFullPolicy::Reject {
+                retry_after: Duration::from_secs(5),
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn queues_connections_up_to_the_capacity() {
+        let limiter = limiter(1, 2);
+
+        let served = limiter.admit().await.unwrap();
+        assert!(matches!(served, Admission::Ready(_)));
+
+        let first = limiter.admit().await.unwrap();
+        let second = limiter.admit().await.unwrap();
+        assert!(matches!(first, Admission::Queued(_)));
+        assert!(matches!(second, Admission::Queued(_)));
+        assert!(limiter.is_full());
+
+        assert_eq!(limiter.admit().await.err(), Some(Duration::from_secs(5)));
+
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.active_connections, 1);
+        assert_eq!(metrics.queue_depth, 2);
+        assert_eq!(metrics.max_queue_depth, 2);
+        assert_eq!(metrics.rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn queued_connections_get_freed_slots() {
+        let limiter = limiter(1, 1);
+
+        let served = limiter.admit().await.unwrap().permit().await;
+        let queued = limiter.admit().await.unwrap();
+
+        drop(served);
+        let _served = queued.permit().await;
+
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.active_connections, 1);
+        assert_eq!(metrics.queue_depth, 0);
+        assert!(!limiter.is_full());
+    }
+
+    #[tokio::test]
+    async fn rejects_right_away_without_a_queue() {
+        let limiter = limiter(1, 0);
+
+        let _served = limiter.admit().await.unwrap();
+
+        assert!(limiter.admit().await.is_err());
+    }
+}
@@ -1,12 +1,21 @@
-use std::{
-    collections::HashMap,
-    io::{BufRead, BufReader, Read},
-    net::TcpStream,
-};
+use std::{any::Any, collections::HashMap, sync::Arc};
 
 use bytes::Bytes;
+use nom::error::{VerboseError, VerboseErrorKind};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    time::{self, Instant},
+};
+
+use crate::{
+    parser::{self, Chunk, RequestHead, RequestLine, RequestTarget},
+    router::{ErrorRenderer, HttpRequestParams, RouteNames, UrlError},
+    shared::{HttpEncodingScheme, HttpHeaders},
+    state::SharedState,
+};
 
-use crate::shared::{HttpEncodingScheme, HttpHeaders};
+const READ_CHUNK_SIZE: usize = 4096;
+const MAX_HEAD_SIZE: usize = 64 * 1024;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum HttpMethod {
@@ -16,6 +25,52 @@ pub enum HttpMethod {
     DELETE,
     HEAD,
     OPTIONS,
+    PATCH,
+    TRACE,
+    CONNECT,
+    /// Extension methods such as WebDAV's `PROPFIND`.
+    Other(String),
+}
+
+impl HttpMethod {
+    pub fn as_str(self: &Self) -> &str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::TRACE => "TRACE",
+            HttpMethod::CONNECT => "CONNECT",
+            HttpMethod::Other(method) => method,
+        }
+    }
+}
+
+impl From<&str> for HttpMethod {
+    /// Method names are case-sensitive, so `get` is an extension method.
+    fn from(method: &str) -> Self {
+        match method {
+            "GET" => HttpMethod::GET,
+            "POST" => HttpMethod::POST,
+            "PUT" => HttpMethod::PUT,
+            "DELETE" => HttpMethod::DELETE,
+            "HEAD" => HttpMethod::HEAD,
+            "OPTIONS" => HttpMethod::OPTIONS,
+            "PATCH" => HttpMethod::PATCH,
+            "TRACE" => HttpMethod::TRACE,
+            "CONNECT" => HttpMethod::CONNECT,
+            method => HttpMethod::Other(method.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 type HttpQueryParams = HashMap<String, String>;
@@ -26,32 +81,198 @@ trait FromQueryString: Sized {
 
 impl FromQueryString for HttpQueryParams {
     fn from_query_string(query_string: &str) -> Result<Self, HttpRequestParsingError> {
-        let entries = query_string
-            .split("&")
-            .map(|tuple| {
-                tuple
-                    .split_once("=")
-                    .map(|(key, value)| (key.to_string(), value.to_string()))
-            })
-            .map(|split| {
-                split.ok_or(HttpRequestParsingError(
-                    "Incorrect query string format".into(),
-                ))
-            })
-            .collect::<Result<Vec<(String, String)>, HttpRequestParsingError>>()?;
+        let (_, entries) = parser::query_string(query_string)
+            .map_err(|_| HttpRequestParsingError("Incorrect query string format".into()))?;
+
+        Ok(HashMap::<String, String>::from_iter(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+        ))
+    }
+}
 
-        Ok(HashMap::<String, String>::from_iter(entries))
+#[derive(Debug, Clone, PartialEq)]
+pub enum HttpRequestTarget {
+    /// `/path?query`, the form used when talking to an origin server.
+    Origin { path: String, query: Option<String> },
+    /// `http://host/path?query`, the form used when talking to a proxy.
+    Absolute {
+        scheme: String,
+        authority: String,
+        path: String,
+        query: Option<String>,
+    },
+    /// `host:port`, only used with `CONNECT`.
+    Authority(String),
+    /// `*`, only used with a server-wide `OPTIONS`.
+    Asterisk,
+}
+
+impl HttpRequestTarget {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn scheme(self: &Self) -> Option<&str> {
+        match self {
+            HttpRequestTarget::Absolute { scheme, .. } => Some(scheme),
+            _ => None,
+        }
+    }
+
+    pub fn authority(self: &Self) -> Option<&str> {
+        match self {
+            HttpRequestTarget::Absolute { authority, .. } => Some(authority),
+            HttpRequestTarget::Authority(authority) => Some(authority),
+            _ => None,
+        }
+    }
+
+    /// The path to route on: `*` for asterisk-form and empty for
+    /// authority-form targets, which have no path of their own.
+    pub fn path(self: &Self) -> &str {
+        match self {
+            HttpRequestTarget::Origin { path, .. } => path,
+            HttpRequestTarget::Absolute { path, .. } => path,
+            HttpRequestTarget::Authority(_) => "",
+            HttpRequestTarget::Asterisk => "*",
+        }
+    }
+
+    /// The same target with another path, as seen by a router mounted
+    /// under a prefix. Targets without a path are left as they are.
+    pub fn with_path(self: Self, new_path: String) -> Self {
+        match self {
+            HttpRequestTarget::Origin { query, .. } => HttpRequestTarget::Origin {
+                path: new_path,
+                query,
+            },
+            HttpRequestTarget::Absolute {
+                scheme,
+                authority,
+                query,
+                ..
+            } => HttpRequestTarget::Absolute {
+                scheme,
+                authority,
+                path: new_path,
+                query,
+            },
+            target => target,
+        }
+    }
+
+    pub fn query(self: &Self) -> Option<&str> {
+        match self {
+            HttpRequestTarget::Origin { query, .. } => query.as_deref(),
+            HttpRequestTarget::Absolute { query, .. } => query.as_deref(),
+            _ => None,
+        }
     }
 }
 
-#[derive(Debug)]
+impl From<RequestTarget<'_>> for HttpRequestTarget {
+    fn from(target: RequestTarget) -> Self {
+        match target {
+            RequestTarget::Origin { path, query } => HttpRequestTarget::Origin {
+                path: path.to_string(),
+                query: query.map(str::to_string),
+            },
+            RequestTarget::Absolute {
+                scheme,
+                authority,
+                path,
+                query,
+            } => HttpRequestTarget::Absolute {
+                scheme: scheme.to_lowercase(),
+                authority: authority.to_string(),
+                path: path.to_string(),
+                query: query.map(str::to_string),
+            },
+            RequestTarget::Authority(authority) => {
+                HttpRequestTarget::Authority(authority.to_string())
+            }
+            RequestTarget::Asterisk => HttpRequestTarget::Asterisk,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct HttpRequest {
     pub method: HttpMethod,
-    pub target: String,
+    pub target: HttpRequestTarget,
+    pub version: (u8, u8),
     pub headers: HttpHeaders,
     pub query: Option<HttpQueryParams>,
     pub body: Option<Bytes>,
     pub accepted_encodings: Vec<HttpEncodingScheme>,
+    /// Set by the router that dispatches the request, if it has any state.
+    pub state: Option<SharedState>,
+    /// Set by the outermost router that dispatches the request, so that
+    /// handlers can build URLs for its named routes.
+    pub route_names: Option<Arc<RouteNames>>,
+    /// Set by the router that dispatches the request, if it has an error
+    /// renderer, to render the errors its handler returns.
+    pub error_renderer: Option<Arc<dyn ErrorRenderer>>,
+}
+
+impl HttpRequest {
+    /// The state shared by the router that dispatched this request, if it is
+    /// of type `T`.
+    pub fn state<T: Any + Send + Sync>(self: &Self) -> Option<Arc<T>> {
+        self.state.as_ref()?.get()
+    }
+
+    /// The path of the route called `name`, with `params` filled in and
+    /// percent-encoded.
+    pub fn url_for(
+        self: &Self,
+        name: &str,
+        params: &HttpRequestParams,
+    ) -> Result<String, UrlError> {
+        match &self.route_names {
+            Some(names) => names.url_for(name, params),
+            None => Err(UrlError::UnknownRoute(name.to_string())),
+        }
+    }
+
+    /// The host name the request is addressed to, without any port and in
+    /// lowercase. An absolute-form target takes precedence over the `Host`
+    /// header (RFC 9112, section 3.2.2).
+    pub fn host(self: &Self) -> Option<String> {
+        let authority = match self.target.authority() {
+            Some(authority) => authority,
+            None => self.headers.get("host")?,
+        };
+
+        let authority = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+
+        let host = match authority.strip_prefix('[') {
+            Some(ipv6) => ipv6.split_once(']').map_or(ipv6, |(host, _)| host),
+            None => authority
+                .split_once(':')
+                .map_or(authority, |(host, _)| host),
+        };
+
+        Some(host.to_lowercase())
+    }
+
+    /// Whether the client wants the connection kept open after this request:
+    /// the default for HTTP/1.1, and opt-in for HTTP/1.0.
+    pub fn keep_alive(self: &Self) -> bool {
+        let connection_has = |option: &str| {
+            self.headers.get("connection").is_some_and(|connection| {
+                connection
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case(option))
+            })
+        };
+
+        match self.version {
+            (1, 0) => connection_has("keep-alive"),
+            _ => !connection_has("close"),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -63,10 +284,38 @@ impl HttpRequestParsingError {
     }
 }
 
+impl From<nom::Err<VerboseError<&[u8]>>> for HttpRequestParsingError {
+    fn from(err: nom::Err<VerboseError<&[u8]>>) -> Self {
+        let errors = match err {
+            nom::Err::Incomplete(_) => return HttpRequestParsingError("Incomplete request".into()),
+            nom::Err::Error(err) | nom::Err::Failure(err) => err.errors,
+        };
+
+        let context = errors.into_iter().find_map(|(_, kind)| match kind {
+            VerboseErrorKind::Context(context) => Some(context),
+            _ => None,
+        });
+
+        HttpRequestParsingError(format!(
+            "Malformed request: invalid {}",
+            context.unwrap_or("syntax")
+        ))
+    }
+}
+
+/// How the length of the request body is determined (RFC 9112, section 6.3).
+#[derive(Debug, PartialEq)]
+enum BodyFraming {
+    None,
+    ContentLength(usize),
+    Chunked,
+}
+
 #[derive(Debug)]
 pub struct HttpRequestBuilder {
     _method: HttpMethod,
-    _target: String,
+    _target: HttpRequestTarget,
+    _version: (u8, u8),
     _headers: HttpHeaders,
     _query: Option<HttpQueryParams>,
     _body: Option<Bytes>,
@@ -74,52 +323,40 @@ pub struct HttpRequestBuilder {
 }
 
 impl HttpRequestBuilder {
-    pub fn from_request_line(
-        request_line: impl AsRef<str>,
-    ) -> Result<Self, HttpRequestParsingError> {
-        let mut segments = request_line.as_ref().split(" ");
-        let method = match segments.next() {
-            Some("GET") => Ok(HttpMethod::GET),
-            Some("POST") => Ok(HttpMethod::POST),
-            Some("PUT") => Ok(HttpMethod::PUT),
-            Some("DELETE") => Ok(HttpMethod::DELETE),
-            Some("HEAD") => Ok(HttpMethod::HEAD),
-            Some("OPTIONS") => Ok(HttpMethod::OPTIONS),
-            None => Err(HttpRequestParsingError("Missing HTTP verb".to_string())),
-            Some(method) => Err(HttpRequestParsingError(format!(
-                "Invalid HTTP verb: {}",
-                method
-            ))),
-        }?;
-
-        let mut target_string = match segments.next() {
-            Some(target) if target.starts_with("/") => Ok(target.to_string()),
-            Some(_) => Err(HttpRequestParsingError(
-                "Request target must start with /".to_string(),
-            )),
-            None => Err(HttpRequestParsingError(
-                "Missing request target".to_string(),
+    pub fn from_request_line(request_line: RequestLine) -> Result<Self, HttpRequestParsingError> {
+        let method = HttpMethod::from(request_line.method);
+
+        let target = HttpRequestTarget::from(request_line.target);
+
+        match (&method, &target) {
+            (HttpMethod::CONNECT, HttpRequestTarget::Authority(_)) => Ok(()),
+            (HttpMethod::CONNECT, _) | (_, HttpRequestTarget::Authority(_)) => Err(
+                HttpRequestParsingError("Authority-form is only allowed for CONNECT".into()),
+            ),
+            (HttpMethod::OPTIONS, HttpRequestTarget::Asterisk) => Ok(()),
+            (_, HttpRequestTarget::Asterisk) => Err(HttpRequestParsingError(
+                "Asterisk-form is only allowed for OPTIONS".into(),
             )),
+            _ => Ok(()),
         }?;
 
-        let mut target = target_string.clone();
-        let mut query: Option<HttpQueryParams> = None;
-        if let Some((new_target, query_string)) = &target_string.split_once("?") {
-            target = new_target.to_string();
-            query = Some(HttpQueryParams::from_query_string(&query_string)?);
-        }
+        let query = target
+            .query()
+            .map(HttpQueryParams::from_query_string)
+            .transpose()?;
 
-        let version = segments.next();
-        if version != Some("HTTP/1.1\r\n") {
+        if !is_supported_version(request_line.version) {
+            let (major, minor) = request_line.version;
             return Err(HttpRequestParsingError(format!(
-                "Invalid HTTP version or missing CRLF sequence: {}",
-                version.unwrap_or("")
+                "Unsupported HTTP version: {}.{}",
+                major, minor
             )));
         }
 
         Ok(HttpRequestBuilder {
             _method: method,
             _target: target,
+            _version: request_line.version,
             _headers: HttpHeaders::default(),
             _query: query,
             _body: None,
@@ -131,10 +368,6 @@ impl HttpRequestBuilder {
         self._headers.insert(header_name, header_value);
     }
 
-    pub fn body(self: &mut Self, body: impl Into<Bytes>) {
-        self._body = Some(body.into());
-    }
-
     pub fn accept_encoding(self: &mut Self, encoding_name: impl AsRef<str>) {
         let encoding_scheme = match encoding_name.as_ref() {
             "gzip" => Some(HttpEncodingScheme::Gzip),
@@ -150,76 +383,553 @@ impl HttpRequestBuilder {
         HttpRequest {
             method: self._method,
             target: self._target,
+            version: self._version,
             headers: self._headers,
             query: self._query,
             body: self._body,
             accepted_encodings: self._accepted_encodings,
+            state: None,
+            route_names: None,
+            error_renderer: None,
         }
     }
 }
 
+fn parse_content_length(value: &str) -> Result<usize, HttpRequestParsingError> {
+    if value.is_empty() || !value.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(HttpRequestParsingError(
+            "Invalid content-length header".into(),
+        ));
+    }
+
+    str::parse::<usize>(value)
+        .map_err(|_| HttpRequestParsingError("Invalid content-length header".into()))
+}
+
+/// Turns a parsed request head into a builder, working out along the way how
+/// the body that follows it is framed.
+fn process_head(
+    head: RequestHead,
+) -> Result<(HttpRequestBuilder, BodyFraming), HttpRequestParsingError> {
+    let mut builder = HttpRequestBuilder::from_request_line(head.request_line)?;
+
+    let mut content_length: Option<usize> = None;
+    let mut chunked = false;
+    let mut host_count = 0;
+    for (header_name, header_value) in head.headers {
+        let header_name = header_name.to_lowercase();
+
+        if header_name == "content-length" {
+            let value = parse_content_length(header_value)?;
+            if content_length.is_some_and(|length| length != value) {
+                return Err(HttpRequestParsingError(
+                    "Conflicting content-length headers".into(),
+                ));
+            }
+
+            content_length = Some(value);
+        } else if header_name == "transfer-encoding" {
+            if !header_value.eq_ignore_ascii_case("chunked") {
+                return Err(HttpRequestParsingError(format!(
+                    "Unsupported transfer-encoding: {}",
+                    header_value
+                )));
+            }
+
+            chunked = true;
+        } else if header_name == "host" {
+            host_count += 1;
+        } else if header_name == "accept-encoding" {
+            header_value
+                .split(',')
+                .map(|encoding_name| encoding_name.trim_matches([' ', '\t']))
+                .for_each(|encoding_name| builder.accept_encoding(encoding_name));
+        }
+
+        builder.header(header_name, header_value);
+    }
+
+    match (builder._version, host_count) {
+        ((1, 1), 0) => {
+            return Err(HttpRequestParsingError("Missing host header".into()));
+        }
+        (_, 2..) => {
+            return Err(HttpRequestParsingError("Multiple host headers".into()));
+        }
+        _ => {}
+    }
+
+    let framing = match (chunked, content_length) {
+        (true, Some(_)) => {
+            return Err(HttpRequestParsingError(
+                "Both transfer-encoding and content-length are present".into(),
+            ))
+        }
+        (true, None) => BodyFraming::Chunked,
+        (false, Some(length)) if length > 0 => BodyFraming::ContentLength(length),
+        (false, _) => BodyFraming::None,
+    };
+
+    Ok((builder, framing))
+}
+
+/// The part of a request still waiting on the connection once its head has
+/// been read.
+#[derive(Debug)]
+pub struct HttpRequestBody {
+    framing: BodyFraming,
+    /// The largest body that will be read, if there is a limit.
+    max_size: Option<usize>,
+}
+
+impl HttpRequestBody {
+    /// Whether the request has no body at all.
+    pub fn is_empty(self: &Self) -> bool {
+        self.framing == BodyFraming::None
+    }
+
+    /// Limits the body to `max_size` bytes, however it is framed.
+    pub fn max_size(mut self: Self, max_size: usize) -> Self {
+        self.max_size = Some(max_size);
+
+        self
+    }
+
+    /// Whether the body's announced length, if it has one, is within the
+    /// limit. Chunked bodies can only be checked as they are read.
+    pub fn fits(self: &Self) -> bool {
+        match self.framing {
+            BodyFraming::ContentLength(content_length) => self.check_size(content_length).is_ok(),
+            _ => true,
+        }
+    }
+
+    fn check_size(self: &Self, size: usize) -> Result<(), HttpReadError> {
+        match self.max_size {
+            Some(max_size) if size > max_size => Err(HttpReadError::TooLarge),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn is_supported_version(version: (u8, u8)) -> bool {
+    matches!(version, (1, 0) | (1, 1))
+}
+
+/// Why a request could not be read off a connection.
+#[derive(Debug)]
+pub enum HttpReadError {
+    Malformed(HttpRequestParsingError),
+    /// The request is well-formed, but for an HTTP version other than 1.0
+    /// and 1.1.
+    UnsupportedVersion((u8, u8)),
+    /// The read deadline passed before the request was complete.
+    TimedOut,
+    /// The body is larger than the endpoint it is for accepts.
+    TooLarge,
+    /// The peer closed the connection part way through a request.
+    Closed,
+    Io(std::io::Error),
+}
+
+impl From<HttpRequestParsingError> for HttpReadError {
+    fn from(err: HttpRequestParsingError) -> Self {
+        HttpReadError::Malformed(err)
+    }
+}
+
+impl From<nom::Err<VerboseError<&[u8]>>> for HttpReadError {
+    fn from(err: nom::Err<VerboseError<&[u8]>>) -> Self {
+        HttpReadError::Malformed(err.into())
+    }
+}
+
+impl From<std::io::Error> for HttpReadError {
+    fn from(err: std::io::Error) -> Self {
+        HttpReadError::Io(err)
+    }
+}
+
 pub trait HttpRequestReader {
-    fn read_http_req(self: &Self) -> Result<HttpRequest, HttpRequestParsingError>;
+    /// Reads the request line and headers, leaving the body unread so that
+    /// the caller can decide whether it wants it at all. Returns `None` if
+    /// the peer closed the connection cleanly before sending a new request.
+    async fn read_http_head(
+        self: &mut Self,
+    ) -> Result<Option<(HttpRequest, HttpRequestBody)>, HttpReadError>;
+
+    async fn read_http_body(
+        self: &mut Self,
+        body: HttpRequestBody,
+    ) -> Result<Option<Bytes>, HttpReadError>;
 }
 
-impl HttpRequestReader for TcpStream {
-    fn read_http_req(self: &Self) -> Result<HttpRequest, HttpRequestParsingError> {
-        let mut reader = BufReader::new(self);
+/// Reads consecutive requests off a single connection.
+///
+/// Bytes received past the end of one request are kept for the next, so
+/// clients may pipeline several requests without waiting for responses.
+pub struct HttpConnectionReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    deadline: Option<Instant>,
+}
 
-        let mut request_line = String::new();
-        reader
-            .read_line(&mut request_line)
-            .map_err(|err| HttpRequestParsingError(err.to_string()))?;
+impl<R: AsyncRead + Unpin> HttpConnectionReader<R> {
+    pub fn new(inner: R) -> Self {
+        HttpConnectionReader {
+            inner,
+            buf: Vec::new(),
+            deadline: None,
+        }
+    }
 
-        let mut builder = HttpRequestBuilder::from_request_line(request_line)?;
+    /// Bounds all subsequent reads: once `deadline` passes they fail with
+    /// `HttpReadError::TimedOut`, however slowly the peer trickles bytes in.
+    pub fn set_deadline(self: &mut Self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
 
-        let mut content_length = 0;
-        loop {
-            let mut header_line = String::new();
-            reader
-                .read_line(&mut header_line)
-                .map_err(|err| HttpRequestParsingError(err.to_string()))?;
-
-            header_line = header_line
-                .strip_suffix("\r\n")
-                .ok_or(HttpRequestParsingError(
-                    "Missing CRLF sequence after header".into(),
-                ))?
-                .to_owned();
-
-            if header_line.len() == 0 {
-                break;
-            }
+    /// Waits until at least one byte of the next request is available,
+    /// returning `false` if the peer closed the connection instead.
+    pub async fn wait_for_request(self: &mut Self) -> Result<bool, HttpReadError> {
+        if !self.buf.is_empty() {
+            return Ok(true);
+        }
+
+        Ok(self.fill_buf().await? > 0)
+    }
+
+    /// Reads once from the connection and appends whatever arrived to the
+    /// buffer, returning how many bytes were read.
+    async fn fill_buf(self: &mut Self) -> Result<usize, HttpReadError> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        let read = self.inner.read(&mut chunk);
+
+        let n_bytes = match self.deadline {
+            Some(deadline) => time::timeout_at(deadline, read)
+                .await
+                .map_err(|_| HttpReadError::TimedOut)??,
+            None => read.await?,
+        };
+
+        self.buf.extend_from_slice(&chunk[..n_bytes]);
 
-            let (header_name, header_value) = header_line
-                .split_once(": ")
-                .ok_or(HttpRequestParsingError("Incorrect header format".into()))?;
+        Ok(n_bytes)
+    }
 
-            let header_name = header_name.to_lowercase();
-            let header_value = header_value.strip_suffix("\r\n").unwrap_or(header_value);
+    async fn fill_buf_or_fail(self: &mut Self) -> Result<(), HttpReadError> {
+        match self.fill_buf().await? {
+            0 => Err(HttpReadError::Closed),
+            _ => Ok(()),
+        }
+    }
+}
 
-            if header_name == "content-length" {
-                content_length = str::parse::<usize>(header_value)
-                    .map_err(|_| HttpRequestParsingError("Invalid content-length header".into()))?;
-            } else if header_name == "accept-encoding" {
-                header_value
-                    .split(", ")
-                    .for_each(|encoding_name| builder.accept_encoding(encoding_name));
+impl<R: AsyncRead + Unpin> HttpRequestReader for HttpConnectionReader<R> {
+    async fn read_http_head(
+        self: &mut Self,
+    ) -> Result<Option<(HttpRequest, HttpRequestBody)>, HttpReadError> {
+        loop {
+            match parser::request_head(&self.buf) {
+                Ok((rest, head)) => {
+                    let version = head.request_line.version;
+                    if !is_supported_version(version) {
+                        return Err(HttpReadError::UnsupportedVersion(version));
+                    }
+
+                    let consumed = self.buf.len() - rest.len();
+                    let (builder, framing) = process_head(head)?;
+                    self.buf.drain(..consumed);
+
+                    let body = HttpRequestBody {
+                        framing,
+                        max_size: None,
+                    };
+
+                    return Ok(Some((builder.build(), body)));
+                }
+                Err(nom::Err::Incomplete(_)) if self.buf.len() < MAX_HEAD_SIZE => {
+                    if self.fill_buf().await? == 0 {
+                        return match self.buf.is_empty() {
+                            true => Ok(None),
+                            false => Err(HttpReadError::Closed),
+                        };
+                    }
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err(HttpRequestParsingError("Request head too large".into()).into())
+                }
+                Err(err) => return Err(err.into()),
             }
+        }
+    }
 
-            builder.header(header_name, header_value);
+    async fn read_http_body(
+        self: &mut Self,
+        body: HttpRequestBody,
+    ) -> Result<Option<Bytes>, HttpReadError> {
+        match body.framing {
+            BodyFraming::None => Ok(None),
+            BodyFraming::ContentLength(content_length) => {
+                body.check_size(content_length)?;
+
+                while self.buf.len() < content_length {
+                    self.fill_buf_or_fail().await?;
+                }
+
+                let body = self.buf.drain(..content_length).collect::<Vec<_>>();
+                Ok(Some(body.into()))
+            }
+            BodyFraming::Chunked => {
+                // Chunks are drained from the buffer as soon as they are
+                // complete, so that each read only parses what is new.
+                let mut data = Vec::new();
+
+                loop {
+                    match parser::chunk(&self.buf) {
+                        Ok((rest, chunk)) => {
+                            let consumed = self.buf.len() - rest.len();
+
+                            let done = match chunk {
+                                Chunk::Data(chunk) => {
+                                    body.check_size(data.len() + chunk.len())?;
+                                    data.extend_from_slice(chunk);
+                                    false
+                                }
+                                Chunk::Last(_) => true,
+                            };
+
+                            self.buf.drain(..consumed);
+                            if done {
+                                return Ok(Some(data.into()));
+                            }
+                        }
+                        Err(nom::Err::Incomplete(_)) => {
+                            // Oversized chunks are refused as soon as their
+                            // size is known, rather than buffered first.
+                            if let Ok((_, size)) = parser::chunk_size(&self.buf) {
+                                body.check_size(data.len().saturating_add(size))?;
+                            }
+
+                            self.fill_buf_or_fail().await?
+                        }
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            }
         }
+    }
+}
 
-        if content_length > 0 {
-            let mut body = vec![0u8; content_length];
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            reader
-                .read_exact(&mut body)
-                .map_err(|err| HttpRequestParsingError(err.to_string()))?;
+    /// How the body of a request with the given header lines is framed, or
+    /// why the request is rejected.
+    fn framing(fields: &str) -> Result<BodyFraming, String> {
+        let input = format!("POST / HTTP/1.1\r\nHost: x\r\n{}\r\n", fields);
+        let (_, head) = parser::request_head(input.as_bytes()).unwrap();
 
-            builder.body(body);
-        }
+        process_head(head)
+            .map(|(_, framing)| framing)
+            .map_err(|err| err.message())
+    }
+
+    #[test]
+    fn keeps_the_parts_of_absolute_targets() {
+        let (_, target) = parser::request_target("HTTP://Example.com:8080/a?b=c").unwrap();
+        let target = HttpRequestTarget::from(target);
+
+        assert_eq!(target.scheme(), Some("http"));
+        assert_eq!(target.authority(), Some("Example.com:8080"));
+        assert_eq!(target.path(), "/a");
+        assert_eq!(target.query(), Some("b=c"));
+    }
+
+    fn head(input: &str) -> Result<HttpRequest, HttpRequestParsingError> {
+        let (_, head) = parser::request_head(input.as_bytes()).unwrap();
+
+        process_head(head).map(|(builder, _)| builder.build())
+    }
+
+    #[test]
+    fn keeps_authority_form_for_connect() {
+        let req = head("CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n");
+        assert_eq!(
+            req.unwrap().target,
+            HttpRequestTarget::Authority("example.com:443".into())
+        );
+
+        assert!(head("CONNECT / HTTP/1.1\r\nHost: x\r\n\r\n").is_err());
+        assert!(head("CONNECT http://x:1/ HTTP/1.1\r\nHost: x\r\n\r\n").is_err());
+        assert!(head("GET example.com:443 HTTP/1.1\r\nHost: x\r\n\r\n").is_err());
+        assert!(head("GET * HTTP/1.1\r\nHost: x\r\n\r\n").is_err());
+        assert!(head("OPTIONS * HTTP/1.1\r\nHost: x\r\n\r\n").is_ok());
+    }
+
+    #[test]
+    fn reads_the_host_name() {
+        let host = |target: &str, host: &str| {
+            head(&format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n",
+                target, host
+            ))
+            .unwrap()
+            .host()
+        };
+
+        assert_eq!(host("/", "Example.COM"), Some("example.com".into()));
+        assert_eq!(host("/", "example.com:8080"), Some("example.com".into()));
+        assert_eq!(
+            host("/", "user:pw@example.com:8080"),
+            Some("example.com".into())
+        );
+        assert_eq!(host("/", "[::1]"), Some("::1".into()));
+        assert_eq!(host("/", "[::1]:8080"), Some("::1".into()));
+        assert_eq!(
+            host("/", "user@[2001:DB8::1]:80"),
+            Some("2001:db8::1".into())
+        );
+
+        // An absolute-form target's authority wins over the Host header.
+        assert_eq!(
+            host("http://user@other.test:81/a", "example.com"),
+            Some("other.test".into())
+        );
+    }
+
+    #[test]
+    fn frames_by_content_length() {
+        assert_eq!(
+            framing("Content-Length: 5\r\n"),
+            Ok(BodyFraming::ContentLength(5))
+        );
+        assert_eq!(framing("Content-Length: 0\r\n"), Ok(BodyFraming::None));
+        assert_eq!(framing(""), Ok(BodyFraming::None));
+    }
+
+    #[test]
+    fn frames_chunked_bodies() {
+        assert_eq!(
+            framing("Transfer-Encoding: chunked\r\n"),
+            Ok(BodyFraming::Chunked)
+        );
+        assert_eq!(
+            framing("Transfer-Encoding: CHUNKED\r\n"),
+            Ok(BodyFraming::Chunked)
+        );
+    }
+
+    #[test]
+    fn accepts_repeated_identical_content_length() {
+        assert_eq!(
+            framing("Content-Length: 5\r\nContent-Length: 5\r\n"),
+            Ok(BodyFraming::ContentLength(5))
+        );
+    }
+
+    #[test]
+    fn rejects_conflicting_content_length() {
+        assert!(framing("Content-Length: 5\r\nContent-Length: 6\r\n").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_content_length() {
+        assert!(framing("Content-Length: 5, 5\r\n").is_err());
+        assert!(framing("Content-Length: +5\r\n").is_err());
+        assert!(framing("Content-Length: -1\r\n").is_err());
+        assert!(framing("Content-Length: 0x10\r\n").is_err());
+        assert!(framing("Content-Length: \r\n").is_err());
+        assert!(framing("Content-Length: 99999999999999999999999\r\n").is_err());
+    }
+
+    #[test]
+    fn rejects_content_length_with_transfer_encoding() {
+        assert!(framing("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n").is_err());
+        assert!(framing("Transfer-Encoding: chunked\r\nContent-Length: 5\r\n").is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_transfer_encoding() {
+        assert!(framing("Transfer-Encoding: gzip, chunked\r\n").is_err());
+        assert!(framing("Transfer-Encoding: identity\r\n").is_err());
+    }
+
+    #[test]
+    fn rejects_missing_or_repeated_host() {
+        let head = |input: &'static [u8]| {
+            let (_, head) = parser::request_head(input).unwrap();
+            process_head(head).map(|_| ())
+        };
+
+        assert!(head(b"GET / HTTP/1.1\r\n\r\n").is_err());
+        assert!(head(b"GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n").is_err());
+        assert!(head(b"GET / HTTP/1.0\r\n\r\n").is_ok());
+    }
+
+    /// Reads the next request off `reader`, body included, returning its
+    /// path and body.
+    async fn next(
+        reader: &mut HttpConnectionReader<impl AsyncRead + Unpin>,
+    ) -> Option<(String, String)> {
+        let (req, body) = reader.read_http_head().await.unwrap()?;
+        let body = reader
+            .read_http_body(body)
+            .await
+            .unwrap()
+            .unwrap_or_default();
+
+        Some((
+            req.target.path().to_string(),
+            String::from_utf8_lossy(&body).into_owned(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn reads_two_requests_from_one_read() {
+        let input: &[u8] = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTTP/1.1\r\nHost: x\r\n\r\n";
+        let mut reader = HttpConnectionReader::new(input);
+
+        assert_eq!(next(&mut reader).await, Some(("/a".into(), "".into())));
+        assert_eq!(next(&mut reader).await, Some(("/b".into(), "".into())));
+        assert_eq!(next(&mut reader).await, None);
+    }
+
+    #[tokio::test]
+    async fn keeps_bytes_past_a_body_for_the_next_request() {
+        let input: &[u8] = b"POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\nabc\
+            POST /b HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nde\r\n0\r\n\r\n\
+            GET /c HTTP/1.1\r\nHost: x\r\n\r\n";
+        let mut reader = HttpConnectionReader::new(input);
+
+        assert_eq!(next(&mut reader).await, Some(("/a".into(), "abc".into())));
+        assert_eq!(next(&mut reader).await, Some(("/b".into(), "de".into())));
+        assert_eq!(next(&mut reader).await, Some(("/c".into(), "".into())));
+        assert_eq!(next(&mut reader).await, None);
+    }
+
+    #[tokio::test]
+    async fn reads_requests_split_across_reads() {
+        let first: &[u8] = b"POST /a HTTP/1.1\r\nHo";
+        let second: &[u8] = b"st: x\r\nContent-Length: 3\r\n\r\nab";
+        let third: &[u8] = b"cGET /b HTTP/1.1\r\nHost: x\r\n\r\n";
+        let mut reader = HttpConnectionReader::new(first.chain(second).chain(third));
+
+        assert_eq!(next(&mut reader).await, Some(("/a".into(), "abc".into())));
+        assert_eq!(next(&mut reader).await, Some(("/b".into(), "".into())));
+        assert_eq!(next(&mut reader).await, None);
+    }
+
+    #[tokio::test]
+    async fn fails_when_closed_mid_request() {
+        let input: &[u8] = b"POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\nab";
+        let mut reader = HttpConnectionReader::new(input);
 
-        Ok(builder.build())
+        let (_, body) = reader.read_http_head().await.unwrap().unwrap();
+        assert!(matches!(
+            reader.read_http_body(body).await,
+            Err(HttpReadError::Closed)
+        ));
     }
 }
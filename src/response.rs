@@ -1,8 +1,9 @@
-use crate::shared::HttpEncodingScheme;
-use std::{collections::HashMap, io::Write, net::TcpStream};
+use crate::{config::format::Value, router::HttpError, shared::HttpEncodingScheme};
+use std::collections::HashMap;
 
 use bytes::Bytes;
 use libdeflater::{CompressionLvl, Compressor};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 #[derive(Debug)]
 pub struct HttpResponse {
@@ -34,7 +35,7 @@ impl HttpResponseBuilder {
         header_name: impl AsRef<str>,
         header_value: impl AsRef<str>,
     ) -> Self {
-        let header_name = header_name.as_ref().to_lowercase().into();
+        let header_name = header_name.as_ref().to_lowercase();
         let header_value = header_value.as_ref().into();
         self._headers.insert(header_name, header_value);
 
@@ -60,6 +61,15 @@ impl HttpResponseBuilder {
 
     pub fn build(mut self: Self) -> HttpResponse {
         if self._body.is_none() {
+            // Without an explicit length, clients on a persistent connection
+            // would wait for the connection to close to find the end of the
+            // (empty) body. 1xx and 204 responses never carry one.
+            if self._status_code >= 200 && self._status_code != 204 {
+                self._headers
+                    .entry("content-length".into())
+                    .or_insert_with(|| "0".into());
+            }
+
             return HttpResponse {
                 status_code: self._status_code,
                 status_message: self._status_message,
@@ -73,10 +83,9 @@ impl HttpResponseBuilder {
         self._headers
             .insert("content-length".into(), content_length.to_string());
 
-        if self._headers.get("content-type").is_none() {
-            self._headers
-                .insert("content-type".into(), "text/plain".into());
-        }
+        self._headers
+            .entry("content-type".into())
+            .or_insert_with(|| "text/plain".into());
 
         body = match self._encoding {
             HttpEncodingScheme::None => body,
@@ -89,12 +98,12 @@ impl HttpResponseBuilder {
             }
         };
 
-        return HttpResponse {
+        HttpResponse {
             status_code: self._status_code,
             status_message: self._status_message,
             headers: self._headers,
             body: Some(body),
-        };
+        }
     }
 }
 
@@ -110,49 +119,391 @@ impl Default for HttpResponseBuilder {
     }
 }
 
+/// The standard reason phrase for a status code (RFC 9110, section 15).
+pub fn reason_phrase(status_code: usize) -> Option<&'static str> {
+    let phrase = match status_code {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        422 => "Unprocessable Content",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => return None,
+    };
+
+    Some(phrase)
+}
+
+/// Something a handler can return, which the router turns into the
+/// response.
+pub trait IntoResponse {
+    fn into_response(self: Self) -> HttpResponse;
+
+    /// The response, or the error to render instead, which is how the router
+    /// takes what a handler returns.
+    fn into_result(self: Self) -> Result<HttpResponse, HttpError>
+    where
+        Self: Sized,
+    {
+        Ok(self.into_response())
+    }
+}
+
+impl IntoResponse for HttpResponse {
+    fn into_response(self: Self) -> HttpResponse {
+        self
+    }
+}
+
+impl IntoResponse for HttpResponseBuilder {
+    fn into_response(self: Self) -> HttpResponse {
+        self.build()
+    }
+}
+
+/// An empty `200 OK`.
+impl IntoResponse for () {
+    fn into_response(self: Self) -> HttpResponse {
+        HttpResponseBuilder::default()
+            .status(200, reason_phrase(200))
+            .build()
+    }
+}
+
+fn body_response(body: impl Into<Bytes>, content_type: &str) -> HttpResponse {
+    HttpResponseBuilder::default()
+        .status(200, reason_phrase(200))
+        .header("Content-Type", content_type)
+        .body(body)
+        .build()
+}
+
+impl IntoResponse for String {
+    fn into_response(self: Self) -> HttpResponse {
+        body_response(self, "text/plain")
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self: Self) -> HttpResponse {
+        body_response(self, "text/plain")
+    }
+}
+
+impl IntoResponse for Bytes {
+    fn into_response(self: Self) -> HttpResponse {
+        body_response(self, "application/octet-stream")
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self: Self) -> HttpResponse {
+        body_response(self, "application/octet-stream")
+    }
+}
+
+/// A body with another status than `200 OK`.
+impl<B: IntoResponse> IntoResponse for (usize, B) {
+    fn into_response(self: Self) -> HttpResponse {
+        let (status_code, body) = self;
+        let mut res = body.into_response();
+
+        res.status_code = status_code;
+        res.status_message = reason_phrase(status_code).map(Into::into);
+
+        res
+    }
+}
+
+/// A body with another status and extra headers, which replace any the body
+/// set itself.
+impl<H, K, V, B> IntoResponse for (usize, H, B)
+where
+    H: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+    B: IntoResponse,
+{
+    fn into_response(self: Self) -> HttpResponse {
+        let (status_code, headers, body) = self;
+        let mut res = (status_code, body).into_response();
+
+        for (name, value) in headers {
+            res.headers
+                .insert(name.as_ref().to_lowercase(), value.as_ref().into());
+        }
+
+        res
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self: Self) -> HttpResponse {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+
+    fn into_result(self: Self) -> Result<HttpResponse, HttpError> {
+        match self {
+            Ok(value) => value.into_result(),
+            Err(err) => err.into_result(),
+        }
+    }
+}
+
+/// A value that can be sent as JSON, through `Json`.
+#[cfg_attr(not(test), allow(dead_code))]
+pub trait ToJson {
+    fn to_json(self: &Self) -> Value;
+}
+
+impl ToJson for Value {
+    fn to_json(self: &Self) -> Value {
+        self.clone()
+    }
+}
+
+impl ToJson for String {
+    fn to_json(self: &Self) -> Value {
+        Value::String(self.clone())
+    }
+}
+
+impl ToJson for &str {
+    fn to_json(self: &Self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+impl ToJson for bool {
+    fn to_json(self: &Self) -> Value {
+        Value::Bool(*self)
+    }
+}
+
+impl ToJson for i64 {
+    fn to_json(self: &Self) -> Value {
+        Value::Integer(*self)
+    }
+}
+
+impl ToJson for i32 {
+    fn to_json(self: &Self) -> Value {
+        Value::Integer((*self).into())
+    }
+}
+
+impl ToJson for u32 {
+    fn to_json(self: &Self) -> Value {
+        Value::Integer((*self).into())
+    }
+}
+
+impl ToJson for usize {
+    fn to_json(self: &Self) -> Value {
+        match i64::try_from(*self) {
+            Ok(integer) => Value::Integer(integer),
+            Err(_) => Value::String(self.to_string()),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(self: &Self) -> Value {
+        match self {
+            Some(value) => value.to_json(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(self: &Self) -> Value {
+        Value::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+/// An object, with its keys sorted so that the output is stable.
+impl<T: ToJson> ToJson for HashMap<String, T> {
+    fn to_json(self: &Self) -> Value {
+        let mut entries = self
+            .iter()
+            .map(|(key, value)| (key.clone(), value.to_json()))
+            .collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Value::Table(entries)
+    }
+}
+
 pub trait HttpResponseWriter {
-    fn write_http_res(
+    async fn write_http_res(
         self: &mut Self,
         res: HttpResponse,
     ) -> std::result::Result<usize, std::io::Error>;
 }
 
-impl HttpResponseWriter for TcpStream {
-    fn write_http_res(
+impl<W: AsyncWrite + Unpin> HttpResponseWriter for W {
+    async fn write_http_res(
         self: &mut Self,
         res: HttpResponse,
     ) -> std::result::Result<usize, std::io::Error> {
-        let mut n_bytes = 0;
-
-        let status_line = format!(
+        // The status line and headers go out in a single write rather than
+        // one per line.
+        let mut head = format!(
             "HTTP/1.1 {} {}\r\n",
             res.status_code,
             res.status_message.unwrap_or("".to_owned())
         );
-        n_bytes += self.write(&status_line.into_bytes())?;
 
         for (header_name, header_value) in res.headers.into_iter() {
-            let header = format!("{}: {}\r\n", header_name, header_value);
-            n_bytes += self.write(&header.into_bytes())?;
+            head.push_str(&format!("{}: {}\r\n", header_name, header_value));
         }
 
-        n_bytes += self.write(b"\r\n")?;
+        head.push_str("\r\n");
+        self.write_all(head.as_bytes()).await?;
+        let mut n_bytes = head.len();
 
         if let Some(body) = res.body {
-            n_bytes += self.write(&body)?;
+            self.write_all(&body).await?;
+            n_bytes += body.len();
         }
 
+        self.flush().await?;
+
         Ok(n_bytes)
     }
 }
 
-fn gzip_encode(content_length: usize, payload: impl Into<Bytes>) -> (usize, Bytes) {
+pub fn gzip_encode(content_length: usize, payload: impl Into<Bytes>) -> (usize, Bytes) {
     let mut decompressor = Compressor::new(CompressionLvl::fastest());
     let compression_bound = decompressor.gzip_compress_bound(content_length);
     let mut out = vec![0u8; compression_bound];
     let new_content_length = decompressor
         .gzip_compress(&payload.into(), &mut out)
         .unwrap();
+    out.truncate(new_content_length);
 
     (new_content_length, out.into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(res: &HttpResponse) -> &[u8] {
+        res.body.as_deref().unwrap_or_default()
+    }
+
+    #[test]
+    fn picks_content_types_by_body() {
+        for (res, content_type) in [
+            ("a".to_string().into_response(), "text/plain"),
+            ("a".into_response(), "text/plain"),
+            (b"a".to_vec().into_response(), "application/octet-stream"),
+            (
+                Bytes::from_static(b"a").into_response(),
+                "application/octet-stream",
+            ),
+        ] {
+            assert_eq!(res.status_code, 200);
+            assert_eq!(res.headers["content-type"], content_type);
+            assert_eq!(body(&res), b"a");
+        }
+
+        let res = ().into_response();
+        assert_eq!((res.status_code, res.body), (200, None));
+    }
+
+    #[test]
+    fn sets_the_status() {
+        let res = (201, "made").into_response();
+
+        assert_eq!(res.status_code, 201);
+        assert_eq!(res.status_message.as_deref(), Some("Created"));
+        assert_eq!(body(&res), b"made");
+
+        let res = (299, ()).into_response();
+        assert_eq!((res.status_code, res.status_message), (299, None));
+    }
+
+    #[test]
+    fn adds_headers_over_those_of_the_body() {
+        let res = (202, [("Content-Type", "text/csv"), ("X-Job", "7")], "a,b").into_response();
+
+        assert_eq!(res.status_code, 202);
+        assert_eq!(res.headers["content-type"], "text/csv");
+        assert_eq!(res.headers["x-job"], "7");
+        assert_eq!(body(&res), b"a,b");
+
+        let res = (200, Vec::<(String, String)>::new(), "a").into_response();
+        assert_eq!(res.headers["content-type"], "text/plain");
+    }
+
+    #[test]
+    fn answers_with_either_side_of_a_result() {
+        let ok: Result<&str, (usize, &str)> = Ok("yes");
+        let err: Result<&str, (usize, &str)> = Err((400, "no"));
+
+        assert_eq!(body(&ok.into_response()), b"yes");
+
+        let res = err.into_response();
+        assert_eq!((res.status_code, body(&res)), (400, &b"no"[..]));
+    }
+
+    #[test]
+    fn keeps_http_errors_for_the_router_to_render() {
+        let ok: Result<&str, HttpError> = Ok("yes");
+        assert_eq!(body(&ok.into_result().unwrap()), b"yes");
+
+        let err: Result<&str, HttpError> = Err(HttpError::status(404).body("gone"));
+        let err = err.into_result().unwrap_err();
+        assert_eq!((err.status_code(), err.detail()), (404, Some("gone")));
+
+        // Rendered on the spot, an error comes out as plain text.
+        let err: Result<&str, HttpError> = Err(HttpError::status(404).body("gone"));
+        let res = err.into_response();
+        assert_eq!(res.status_code, 404);
+        assert_eq!(res.headers["content-type"], "text/plain");
+        assert_eq!(body(&res), b"gone");
+
+        // Other errors are responses like any other.
+        let err: Result<&str, (usize, &str)> = Err((400, "no"));
+        assert_eq!(err.into_result().unwrap().status_code, 400);
+    }
+}
@@ -0,0 +1,67 @@
+//! A rough comparison of how fast `TrieRouter` and `RegexRouter` route
+//! requests as the number of routes grows. Run with
+//! `cargo test --release compare_routers -- --ignored --nocapture`.
+
+use std::time::{Duration, Instant};
+
+use crate::{request::HttpMethod, response::HttpResponseBuilder};
+
+use super::{testing, HttpRegexEndpoint, HttpRouter, RegexRouter, TrieRouter};
+
+const ROUNDS: usize = 20_000;
+
+/// `count` routes spread over a few shapes, half static and half with a
+/// param.
+fn endpoints(count: usize) -> Vec<HttpRegexEndpoint> {
+    (0..count)
+        .map(|i| {
+            let path = match i % 2 {
+                0 => format!("/api/v1/resource{}/items", i),
+                _ => format!("/api/v1/resource{}/:id", i),
+            };
+
+            HttpRegexEndpoint::new(HttpMethod::GET, path, || {
+                HttpResponseBuilder::default().build()
+            })
+        })
+        .collect()
+}
+
+/// Average time to dispatch a request for each of `paths` in turn.
+async fn measure(router: &impl HttpRouter, paths: &[String]) -> Duration {
+    let started = Instant::now();
+
+    for round in 0..ROUNDS {
+        let req = testing::request("GET", &paths[round % paths.len()]);
+        let res = router.dispatch(req).await;
+        assert_eq!(res.status_code, 200);
+    }
+
+    started.elapsed() / ROUNDS as u32
+}
+
+#[tokio::test]
+#[ignore = "benchmark, run explicitly in release mode"]
+async fn compare_routers() {
+    println!("{:>8} {:>12} {:>12}", "routes", "trie", "regex");
+
+    for count in [10, 100, 1000] {
+        // The last routes registered are the worst case for the regex
+        // router, which tries them in order.
+        let paths = [count - 2, count - 1]
+            .iter()
+            .map(|i| match i % 2 {
+                0 => format!("/api/v1/resource{}/items", i),
+                _ => format!("/api/v1/resource{}/42", i),
+            })
+            .collect::<Vec<_>>();
+
+        let trie = TrieRouter::new(endpoints(count)).unwrap();
+        let regex = RegexRouter::new(endpoints(count)).unwrap();
+
+        let trie = measure(&trie, &paths).await;
+        let regex = measure(&regex, &paths).await;
+
+        println!("{:>8} {:>12.2?} {:>12.2?}", count, trie, regex);
+    }
+}
@@ -0,0 +1,390 @@
+//! Errors handlers return, and how they are turned into responses.
+//!
+//! An `HttpError` carries a status, an optional body and headers for the
+//! client, and optionally the error that caused it, which is logged but
+//! never sent. Routers render it with their `ErrorRenderer`: plain text by
+//! default, or problem details (RFC 9457) or an HTML page.
+
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display},
+    io,
+    num::{ParseFloatError, ParseIntError},
+    str::{FromStr, ParseBoolError, Utf8Error},
+};
+
+use crate::{
+    config::format::Value,
+    response::{reason_phrase, HttpResponse, HttpResponseBuilder, IntoResponse},
+};
+
+use super::UrlError;
+
+#[derive(Debug)]
+pub struct HttpError {
+    status_code: usize,
+    status_message: Option<String>,
+    /// What went wrong, for the client.
+    body: Option<String>,
+    headers: Vec<(String, String)>,
+    /// What went wrong, for the logs.
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl HttpError {
+    pub fn new(status_code: usize, status_message: Option<impl AsRef<str>>) -> Self {
+        HttpError {
+            status_code,
+            status_message: status_message.map(|message| message.as_ref().to_owned()),
+            body: None,
+            headers: vec![],
+            source: None,
+        }
+    }
+
+    /// An error with the standard reason phrase for `status_code`.
+    pub fn status(status_code: usize) -> Self {
+        HttpError::new(status_code, None::<&str>)
+    }
+
+    /// Explains the error to the client.
+    pub fn body(mut self: Self, body: impl Into<String>) -> Self {
+        self.body = Some(body.into());
+
+        self
+    }
+
+    /// Adds a header to the response, e.g. `WWW-Authenticate` on a 401.
+    pub fn header(mut self: Self, name: impl AsRef<str>, value: impl Into<String>) -> Self {
+        self.headers
+            .push((name.as_ref().to_lowercase(), value.into()));
+
+        self
+    }
+
+    /// Records the error that caused this one, to be logged along with it.
+    pub fn source(mut self: Self, source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        self.source = Some(source.into());
+
+        self
+    }
+
+    pub fn status_code(self: &Self) -> usize {
+        self.status_code
+    }
+
+    pub fn status_message(self: &Self) -> &str {
+        match &self.status_message {
+            Some(message) => message,
+            None => reason_phrase(self.status_code).unwrap_or("Error"),
+        }
+    }
+
+    pub fn detail(self: &Self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    /// The response for this error, with `body` of type `content_type` if
+    /// there is one.
+    pub fn response(self: &Self, body: Option<(&str, String)>) -> HttpResponse {
+        let mut res =
+            HttpResponseBuilder::default().status(self.status_code, Some(self.status_message()));
+
+        if let Some((content_type, body)) = body {
+            res = res.header("Content-Type", content_type).body(body);
+        }
+
+        let mut res = res.build();
+        for (name, value) in &self.headers {
+            res.headers.insert(name.clone(), value.clone());
+        }
+
+        res
+    }
+
+    /// Logs the error and its causes: server errors as errors, the rest only
+    /// when debugging.
+    pub fn log(self: &Self) {
+        let mut message = self.to_string();
+        let mut source = Error::source(self);
+        while let Some(err) = source {
+            message.push_str(&format!(": {}", err));
+            source = err.source();
+        }
+
+        match self.status_code {
+            500.. => error!("{}", message),
+            _ => debug!("{}", message),
+        }
+    }
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status_code, self.status_message())?;
+
+        if let Some(body) = &self.body {
+            write!(f, " ({})", body)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn Error + 'static))
+    }
+}
+
+/// Missing files are a 404 and forbidden ones a 403; anything else is the
+/// server's fault.
+impl From<io::Error> for HttpError {
+    fn from(err: io::Error) -> Self {
+        let status_code = match err.kind() {
+            io::ErrorKind::NotFound => 404,
+            io::ErrorKind::PermissionDenied => 403,
+            _ => 500,
+        };
+
+        HttpError::status(status_code).source(err)
+    }
+}
+
+/// Values that fail to parse came from the client, so it is told why.
+macro_rules! impl_from_parse_error {
+    ($($err:ty),*) => {
+        $(
+            impl From<$err> for HttpError {
+                fn from(err: $err) -> Self {
+                    HttpError::status(400).body(err.to_string()).source(err)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_parse_error!(ParseIntError, ParseFloatError, ParseBoolError, Utf8Error);
+
+/// A route that cannot be linked to is a mistake in the server.
+impl From<UrlError> for HttpError {
+    fn from(err: UrlError) -> Self {
+        HttpError::status(500).source(err)
+    }
+}
+
+/// Renders as plain text, for when no router is there to pick a renderer.
+impl IntoResponse for HttpError {
+    fn into_response(self: Self) -> HttpResponse {
+        PlainText.render(&self)
+    }
+
+    fn into_result(self: Self) -> Result<HttpResponse, HttpError> {
+        Err(self)
+    }
+}
+
+/// Turns the errors handlers return into responses.
+pub trait ErrorRenderer: Debug + Send + Sync {
+    fn render(self: &Self, err: &HttpError) -> HttpResponse;
+}
+
+/// The body as plain text, or no body at all if the error has none.
+#[derive(Clone, Copy, Debug)]
+pub struct PlainText;
+
+impl ErrorRenderer for PlainText {
+    fn render(self: &Self, err: &HttpError) -> HttpResponse {
+        err.response(err.detail().map(|body| ("text/plain", body.to_string())))
+    }
+}
+
+/// An `application/problem+json` document (RFC 9457), with the body as its
+/// `detail`.
+#[derive(Clone, Copy, Debug)]
+pub struct ProblemJson;
+
+impl ErrorRenderer for ProblemJson {
+    fn render(self: &Self, err: &HttpError) -> HttpResponse {
+        let mut problem = vec![
+            ("type".to_string(), Value::String("about:blank".into())),
+            (
+                "title".to_string(),
+                Value::String(err.status_message().into()),
+            ),
+            (
+                "status".to_string(),
+                Value::Integer(err.status_code() as i64),
+            ),
+        ];
+
+        if let Some(detail) = err.detail() {
+            problem.push(("detail".to_string(), Value::String(detail.into())));
+        }
+
+        err.response(Some((
+            "application/problem+json",
+            Value::Table(problem).to_json(),
+        )))
+    }
+}
+
+/// A minimal HTML page with the status as its heading.
+#[derive(Clone, Copy, Debug)]
+pub struct Html;
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+impl ErrorRenderer for Html {
+    fn render(self: &Self, err: &HttpError) -> HttpResponse {
+        let title = escape_html(&format!("{} {}", err.status_code(), err.status_message()));
+        let detail = match err.detail() {
+            Some(detail) => format!("<p>{}</p>", escape_html(detail)),
+            None => String::new(),
+        };
+
+        err.response(Some((
+            "text/html; charset=utf-8",
+            format!(
+                "<!DOCTYPE html>\n<html>\n<head><title>{title}</title></head>\n\
+                 <body><h1>{title}</h1>{detail}</body>\n</html>\n"
+            ),
+        )))
+    }
+}
+
+/// The built-in renderers, by the names the `errors.format` setting takes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+    Html,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            "html" => Ok(ErrorFormat::Html),
+            _ => Err(format!(
+                "Unknown error format {:?}, expected text, json or html",
+                format
+            )),
+        }
+    }
+}
+
+impl ErrorRenderer for ErrorFormat {
+    fn render(self: &Self, err: &HttpError) -> HttpResponse {
+        match self {
+            ErrorFormat::Text => PlainText.render(err),
+            ErrorFormat::Json => ProblemJson.render(err),
+            ErrorFormat::Html => Html.render(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(res: &HttpResponse) -> String {
+        String::from_utf8_lossy(res.body.as_deref().unwrap_or_default()).into_owned()
+    }
+
+    /// A 500 caused by something the client must not learn about.
+    fn internal() -> HttpError {
+        HttpError::status(500).source(io::Error::other("password=hunter2"))
+    }
+
+    #[test]
+    fn renders_problem_details() {
+        let res = ProblemJson.render(&HttpError::status(404).body("No \"such\" user"));
+
+        assert_eq!(res.status_code, 404);
+        assert_eq!(res.headers["content-type"], "application/problem+json");
+        assert_eq!(
+            body(&res),
+            r#"{"type":"about:blank","title":"Not Found","status":404,"detail":"No \"such\" user"}"#
+        );
+
+        let res = ProblemJson.render(&HttpError::new(429, Some("Slow Down")));
+        assert_eq!(
+            body(&res),
+            r#"{"type":"about:blank","title":"Slow Down","status":429}"#
+        );
+    }
+
+    #[test]
+    fn escapes_html() {
+        let res = Html.render(&HttpError::new(400, Some("<Bad>")).body("a & 'b' \"c\""));
+
+        assert_eq!(res.headers["content-type"], "text/html; charset=utf-8");
+        assert!(body(&res).contains("<title>400 &lt;Bad&gt;</title>"));
+        assert!(body(&res).contains("<p>a &amp; &#39;b&#39; &quot;c&quot;</p>"));
+    }
+
+    #[test]
+    fn renders_plain_text_without_a_body_by_default() {
+        let res = PlainText.render(&HttpError::status(403));
+
+        assert_eq!(res.status_code, 403);
+        assert_eq!(res.body, None);
+        assert_eq!(res.headers.get("content-type"), None);
+
+        let res = PlainText.render(&HttpError::status(400).body("Bad id"));
+        assert_eq!(res.headers["content-type"], "text/plain");
+        assert_eq!(body(&res), "Bad id");
+    }
+
+    #[test]
+    fn sends_extra_headers_with_every_renderer() {
+        let err = HttpError::status(401).header("WWW-Authenticate", "Basic realm=\"x\"");
+
+        for renderer in [ErrorFormat::Text, ErrorFormat::Json, ErrorFormat::Html] {
+            let res = renderer.render(&err);
+            assert_eq!(res.headers["www-authenticate"], "Basic realm=\"x\"");
+        }
+    }
+
+    #[test]
+    fn maps_io_errors_by_kind() {
+        let status = |kind| HttpError::from(io::Error::from(kind)).status_code();
+
+        assert_eq!(status(io::ErrorKind::NotFound), 404);
+        assert_eq!(status(io::ErrorKind::PermissionDenied), 403);
+        assert_eq!(status(io::ErrorKind::Other), 500);
+        assert_eq!(status(io::ErrorKind::BrokenPipe), 500);
+    }
+
+    #[test]
+    fn never_sends_the_source() {
+        let err = internal();
+        assert!(Error::source(&err).is_some());
+
+        for renderer in [ErrorFormat::Text, ErrorFormat::Json, ErrorFormat::Html] {
+            assert!(!body(&renderer.render(&err)).contains("hunter2"));
+        }
+        assert!(!body(&internal().into_response()).contains("hunter2"));
+    }
+}
@@ -0,0 +1,504 @@
+//! Typed handler arguments, pulled out of the request before the handler
+//! runs.
+//!
+//! Handlers take up to six extractors as arguments, in any order, e.g.
+//! `Path(params): Path<FileParams>` for the route params or
+//! `State(state): State<AppState>` for the router's state. When one fails,
+//! the handler is not called and the request is answered with the
+//! extractor's rejection: in most cases a 400 naming what was missing or
+//! malformed.
+
+use std::{
+    any::{type_name, Any},
+    collections::HashMap,
+    fmt::Display,
+    future::Future,
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{
+    config::format::{self, Value},
+    parser,
+    request::HttpRequest,
+    response::{HttpResponse, HttpResponseBuilder, IntoResponse, ToJson},
+};
+
+use super::{pattern, BoxFuture, HttpError, HttpRequestParams};
+
+/// Why an extractor could not produce its value, and the response to send
+/// instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rejection {
+    status_code: usize,
+    status_message: &'static str,
+    message: String,
+}
+
+impl Rejection {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Rejection {
+            status_code: 400,
+            status_message: "Bad Request",
+            message: message.into(),
+        }
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn unsupported_media_type(message: impl Into<String>) -> Self {
+        Rejection {
+            status_code: 415,
+            status_message: "Unsupported Media Type",
+            message: message.into(),
+        }
+    }
+
+    /// A mistake in how the server is set up rather than in the request, so
+    /// the message is logged instead of sent to the client.
+    pub fn internal(message: impl Into<String>) -> Self {
+        Rejection {
+            status_code: 500,
+            status_message: "Internal Server Error",
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Rejection> for HttpError {
+    fn from(rejection: Rejection) -> Self {
+        let err = HttpError::new(rejection.status_code, Some(rejection.status_message));
+
+        match rejection.status_code {
+            500.. => err.source(rejection.message),
+            _ => err.body(rejection.message),
+        }
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self: Self) -> HttpResponse {
+        HttpError::from(self).into_response()
+    }
+
+    fn into_result(self: Self) -> Result<HttpResponse, HttpError> {
+        Err(self.into())
+    }
+}
+
+/// A handler argument that can be extracted from a request.
+pub trait FromRequest: Sized {
+    fn from_request(req: &HttpRequest, params: &HttpRequestParams) -> Result<Self, Rejection>;
+}
+
+/// The whole request, for handlers that need more than extractors give.
+impl FromRequest for HttpRequest {
+    fn from_request(req: &HttpRequest, _params: &HttpRequestParams) -> Result<Self, Rejection> {
+        Ok(req.clone())
+    }
+}
+
+/// Every route param, as it was matched.
+impl FromRequest for HttpRequestParams {
+    fn from_request(_req: &HttpRequest, params: &HttpRequestParams) -> Result<Self, Rejection> {
+        Ok(params.clone())
+    }
+}
+
+/// Named string values from one part of a request, such as its route params
+/// or query string, parsed into typed fields on demand.
+pub struct Fields {
+    /// What the values are, e.g. "query parameter", for error messages.
+    kind: &'static str,
+    values: HashMap<String, String>,
+}
+
+impl Fields {
+    pub fn new(kind: &'static str, values: HashMap<String, String>) -> Self {
+        Fields { kind, values }
+    }
+
+    /// The value called `name`, parsed as a `T`.
+    pub fn get<T>(self: &Self, name: &str) -> Result<T, Rejection>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.optional(name)?
+            .ok_or_else(|| Rejection::bad_request(format!("Missing {} \"{}\"", self.kind, name)))
+    }
+
+    /// The value called `name`, parsed as a `T`, if there is one.
+    pub fn optional<T>(self: &Self, name: &str) -> Result<Option<T>, Rejection>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let Some(value) = self.values.get(name) else {
+            return Ok(None);
+        };
+
+        value.parse().map(Some).map_err(|err| {
+            Rejection::bad_request(format!("Invalid {} \"{}\": {}", self.kind, name, err))
+        })
+    }
+}
+
+/// A type built from named fields, which `Path`, `Query`, `Headers`, `Form`
+/// and `Json` can then extract.
+pub trait FromFields: Sized {
+    fn from_fields(fields: &Fields) -> Result<Self, Rejection>;
+}
+
+/// All the fields, as they are.
+impl FromFields for HashMap<String, String> {
+    fn from_fields(fields: &Fields) -> Result<Self, Rejection> {
+        Ok(fields.values.clone())
+    }
+}
+
+/// Decodes a value from a query string or form body, where `+` stands for a
+/// space.
+fn form_decode(value: &str) -> String {
+    pattern::percent_decode(&value.replace('+', " "))
+}
+
+/// Rejects requests whose body is not of the given media type.
+#[cfg_attr(not(test), allow(dead_code))]
+fn expect_content_type(req: &HttpRequest, media_type: &str) -> Result<(), Rejection> {
+    let content_type = req
+        .headers
+        .get("content-type")
+        .map(|value| value.split(';').next().unwrap_or("").trim());
+
+    match content_type {
+        Some(content_type) if content_type.eq_ignore_ascii_case(media_type) => Ok(()),
+        _ => Err(Rejection::unsupported_media_type(format!(
+            "Expected a body of type {}",
+            media_type
+        ))),
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+fn body_str(req: &HttpRequest) -> Result<&str, Rejection> {
+    let body = req.body.as_deref().unwrap_or_default();
+
+    std::str::from_utf8(body).map_err(|_| Rejection::bad_request("Body is not valid UTF-8"))
+}
+
+/// The route params, e.g. `:filename` in `/files/:filename`.
+pub struct Path<T>(pub T);
+
+impl<T: FromFields> FromRequest for Path<T> {
+    fn from_request(_req: &HttpRequest, params: &HttpRequestParams) -> Result<Self, Rejection> {
+        T::from_fields(&Fields::new("path parameter", params.clone())).map(Path)
+    }
+}
+
+/// The query string, which may be missing altogether.
+pub struct Query<T>(pub T);
+
+impl<T: FromFields> FromRequest for Query<T> {
+    fn from_request(req: &HttpRequest, _params: &HttpRequestParams) -> Result<Self, Rejection> {
+        let values = req
+            .query
+            .iter()
+            .flatten()
+            .map(|(name, value)| (form_decode(name), form_decode(value)))
+            .collect();
+
+        T::from_fields(&Fields::new("query parameter", values)).map(Query)
+    }
+}
+
+/// The request headers, by lowercase name.
+pub struct Headers<T>(pub T);
+
+impl<T: FromFields> FromRequest for Headers<T> {
+    fn from_request(req: &HttpRequest, _params: &HttpRequestParams) -> Result<Self, Rejection> {
+        let values = req
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        T::from_fields(&Fields::new("header", values)).map(Headers)
+    }
+}
+
+/// An `application/x-www-form-urlencoded` body.
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Form<T>(pub T);
+
+impl<T: FromFields> FromRequest for Form<T> {
+    fn from_request(req: &HttpRequest, _params: &HttpRequestParams) -> Result<Self, Rejection> {
+        expect_content_type(req, "application/x-www-form-urlencoded")?;
+        let (_, entries) = parser::query_string(body_str(req)?)
+            .map_err(|_| Rejection::bad_request("Malformed form body"))?;
+
+        let values = entries
+            .into_iter()
+            .map(|(name, value)| (form_decode(name), form_decode(value)))
+            .collect();
+
+        T::from_fields(&Fields::new("form field", values)).map(Form)
+    }
+}
+
+/// An `application/json` body holding an object. Nested objects are read as
+/// dotted field names, like `user.name`; arrays and nulls are left out.
+///
+/// Handlers can also return it, to answer with `T` as JSON.
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Json<T>(pub T);
+
+impl<T: FromFields> FromRequest for Json<T> {
+    fn from_request(req: &HttpRequest, _params: &HttpRequestParams) -> Result<Self, Rejection> {
+        expect_content_type(req, "application/json")?;
+
+        let entries = format::parse_json(body_str(req)?)
+            .map_err(|err| Rejection::bad_request(format!("Malformed JSON body: {}", err)))?;
+
+        let values = entries
+            .into_iter()
+            .filter_map(|(name, value)| match value {
+                Value::String(value) => Some((name, value)),
+                Value::Integer(value) => Some((name, value.to_string())),
+                Value::Number(value) => Some((name, value)),
+                Value::Bool(value) => Some((name, value.to_string())),
+                Value::Array(_) | Value::Table(_) | Value::Null => None,
+            })
+            .collect();
+
+        T::from_fields(&Fields::new("JSON field", values)).map(Json)
+    }
+}
+
+/// The state given to the router with `with_state`.
+pub struct State<T>(pub Arc<T>);
+
+impl<T: Any + Send + Sync> FromRequest for State<T> {
+    fn from_request(req: &HttpRequest, _params: &HttpRequestParams) -> Result<Self, Rejection> {
+        req.state::<T>().map(State).ok_or_else(|| {
+            Rejection::internal(format!(
+                "Handler expects state of type {}, but the router has none",
+                type_name::<T>()
+            ))
+        })
+    }
+}
+
+impl<T: ToJson> IntoResponse for Json<T> {
+    fn into_response(self: Self) -> HttpResponse {
+        HttpResponseBuilder::default()
+            .status(200, Some("OK"))
+            .header("Content-Type", "application/json")
+            .body(self.0.to_json().to_json())
+            .build()
+    }
+}
+
+/// A plain function taking extractors and returning anything that converts
+/// into a response, as `HttpRegexEndpoint::new` accepts.
+pub trait HandlerFn<Args>: Send + Sync + 'static {
+    fn handle(
+        self: &Self,
+        req: HttpRequest,
+        params: HttpRequestParams,
+    ) -> Result<HttpResponse, HttpError>;
+}
+
+/// An async function taking extractors and returning anything that converts
+/// into a response, as `HttpRegexEndpoint::new_async` accepts.
+pub trait AsyncHandlerFn<Args>: Send + Sync + 'static {
+    fn handle(
+        self: &Self,
+        req: HttpRequest,
+        params: HttpRequestParams,
+    ) -> BoxFuture<'static, Result<HttpResponse, HttpError>>;
+}
+
+macro_rules! impl_handler_fns {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg,)*> HandlerFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + Send + Sync + 'static,
+            R: IntoResponse,
+            $($arg: FromRequest,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn handle(
+                self: &Self,
+                req: HttpRequest,
+                params: HttpRequestParams,
+            ) -> Result<HttpResponse, HttpError> {
+                $(
+                    let $arg = match $arg::from_request(&req, &params) {
+                        Ok(value) => value,
+                        Err(rejection) => return Err(rejection.into()),
+                    };
+                )*
+
+                self($($arg),*).into_result()
+            }
+        }
+
+        impl<F, Fut, $($arg,)*> AsyncHandlerFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoResponse,
+            $($arg: FromRequest,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn handle(
+                self: &Self,
+                req: HttpRequest,
+                params: HttpRequestParams,
+            ) -> BoxFuture<'static, Result<HttpResponse, HttpError>> {
+                $(
+                    let $arg = match $arg::from_request(&req, &params) {
+                        Ok(value) => value,
+                        Err(rejection) => {
+                            let err = rejection.into();
+                            return Box::pin(async move { Err(err) });
+                        }
+                    };
+                )*
+
+                let fut = self($($arg),*);
+
+                Box::pin(async move { fut.await.into_result() })
+            }
+        }
+    };
+}
+
+impl_handler_fns!();
+impl_handler_fns!(T1);
+impl_handler_fns!(T1, T2);
+impl_handler_fns!(T1, T2, T3);
+impl_handler_fns!(T1, T2, T3, T4);
+impl_handler_fns!(T1, T2, T3, T4, T5);
+impl_handler_fns!(T1, T2, T3, T4, T5, T6);
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::super::testing::request;
+    use super::*;
+
+    type Values = HashMap<String, String>;
+
+    /// A POST with `body` of type `content_type`.
+    fn post(content_type: &str, body: &str) -> HttpRequest {
+        let mut req = request("POST", "/");
+        req.headers.insert("content-type", content_type);
+        req.body = Some(Bytes::from(body.to_string()));
+
+        req
+    }
+
+    fn extract<T: FromRequest>(req: &HttpRequest) -> Result<T, Rejection> {
+        T::from_request(req, &HttpRequestParams::new())
+    }
+
+    fn values(entries: &[(&str, &str)]) -> Values {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_json_objects_as_dotted_fields() {
+        let req = post(
+            "application/json; charset=utf-8",
+            r#"{"name": "a b", "age": 3, "admin": false, "user": {"id": "x"}, "tags": []}"#,
+        );
+        let Json(fields) = extract::<Json<Values>>(&req).unwrap();
+
+        assert_eq!(
+            fields,
+            values(&[
+                ("name", "a b"),
+                ("age", "3"),
+                ("admin", "false"),
+                ("user.id", "x")
+            ])
+        );
+    }
+
+    #[test]
+    fn keeps_json_numbers_as_written() {
+        let req = post(
+            "application/json",
+            r#"{"price": 9.99, "mass": -1.5e-3, "big": 123456789012345678901234, "zero": -0}"#,
+        );
+        let Json(fields) = extract::<Json<Values>>(&req).unwrap();
+
+        assert_eq!(
+            fields,
+            values(&[
+                ("price", "9.99"),
+                ("mass", "-1.5e-3"),
+                ("big", "123456789012345678901234"),
+                ("zero", "0")
+            ])
+        );
+    }
+
+    #[test]
+    fn decodes_json_surrogate_pairs() {
+        let req = post(
+            "application/json",
+            r#"{"emoji": "\ud83d\ude00!", "e": "\u00e9"}"#,
+        );
+        let Json(fields) = extract::<Json<Values>>(&req).unwrap();
+
+        assert_eq!(fields, values(&[("emoji", "\u{1f600}!"), ("e", "\u{e9}")]));
+    }
+
+    #[test]
+    fn reads_url_encoded_forms() {
+        let req = post("application/x-www-form-urlencoded", "name=a+b&path=%2Fc");
+        let Form(fields) = extract::<Form<Values>>(&req).unwrap();
+
+        assert_eq!(fields, values(&[("name", "a b"), ("path", "/c")]));
+    }
+
+    #[test]
+    fn rejects_bodies_of_another_type() {
+        let req = post("text/plain", "name=a");
+
+        for rejection in [
+            extract::<Form<Values>>(&req).err().unwrap(),
+            extract::<Json<Values>>(&req).err().unwrap(),
+        ] {
+            assert_eq!(rejection.status_code, 415);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_bodies() {
+        let rejection = extract::<Json<Values>>(&post("application/json", "{"))
+            .err()
+            .unwrap();
+        assert_eq!(rejection.status_code, 400);
+
+        let mut req = post("application/x-www-form-urlencoded", "");
+        req.body = Some(Bytes::from_static(b"name=\xff"));
+
+        let rejection = extract::<Form<Values>>(&req).err().unwrap();
+        assert_eq!(rejection.message, "Body is not valid UTF-8");
+    }
+
+    #[test]
+    fn answers_with_json() {
+        let res = Json(values(&[("b", "2"), ("a", "1")])).into_response();
+
+        assert_eq!(res.headers["content-type"], "application/json");
+        assert_eq!(res.body.unwrap(), r#"{"a":"1","b":"2"}"#.as_bytes());
+    }
+}
@@ -0,0 +1,1004 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt::{self, Debug},
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{
+    request::{HttpMethod, HttpRequest, HttpRequestTarget},
+    response::{HttpResponse, HttpResponseBuilder},
+    state::SharedState,
+};
+use regex::Regex;
+
+#[cfg(test)]
+mod bench;
+mod error;
+pub mod extract;
+mod pattern;
+mod prefix;
+#[cfg(test)]
+pub(crate) mod testing;
+mod trie;
+mod urls;
+
+pub use error::{ErrorFormat, ErrorRenderer, HttpError, PlainText};
+use extract::{AsyncHandlerFn, HandlerFn};
+use pattern::{Part, Segment};
+pub use prefix::PrefixRouter;
+pub use trie::TrieRouter;
+pub use urls::{RouteNames, UrlError};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub trait HttpRouter: Send + Sync {
+    fn dispatch(self: &Self, req: HttpRequest) -> BoxFuture<'_, HttpResponse>;
+
+    /// Decides whether a request should go ahead before its body has been
+    /// read, e.g. to answer `Expect: 100-continue`. The request passed in has
+    /// no body yet; returning an error rejects it, and the error is then
+    /// answered through `reject`.
+    ///
+    /// A request that may go ahead comes back with the largest body its
+    /// endpoint accepts, if the endpoint sets a limit of its own.
+    fn precheck(self: &Self, _req: &HttpRequest) -> Result<Option<usize>, HttpError> {
+        Ok(None)
+    }
+
+    /// Answers a request turned down before its body was read, rendering
+    /// `err` the way the router would have rendered it from a handler.
+    fn reject(self: &Self, req: HttpRequest, err: HttpError) -> BoxFuture<'_, HttpResponse> {
+        Box::pin(async move { render_error(req.error_renderer.as_deref(), err) })
+    }
+
+    /// The named routes this router dispatches to, for building URLs.
+    fn route_names(self: &Self) -> Arc<RouteNames> {
+        Arc::default()
+    }
+
+    /// The path of the route called `name`, with `params` filled in.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn url_for(self: &Self, name: &str, params: &HttpRequestParams) -> Result<String, UrlError> {
+        self.route_names().url_for(name, params)
+    }
+}
+
+/// Routers picked at runtime, like the ones `RouterKind::build` returns.
+impl HttpRouter for Box<dyn HttpRouter> {
+    fn dispatch(self: &Self, req: HttpRequest) -> BoxFuture<'_, HttpResponse> {
+        self.as_ref().dispatch(req)
+    }
+
+    fn precheck(self: &Self, req: &HttpRequest) -> Result<Option<usize>, HttpError> {
+        self.as_ref().precheck(req)
+    }
+
+    fn reject(self: &Self, req: HttpRequest, err: HttpError) -> BoxFuture<'_, HttpResponse> {
+        self.as_ref().reject(req, err)
+    }
+
+    fn route_names(self: &Self) -> Arc<RouteNames> {
+        self.as_ref().route_names()
+    }
+}
+
+pub type HttpRequestParams = HashMap<String, String>;
+
+/// A request handler with state of its own, for when a closure will not do.
+///
+/// Closures returning a `BoxFuture` implement this already, so they can be
+/// passed to `HttpRegexEndpoint::from_handler` as they are.
+pub trait Handler: Send + Sync {
+    fn call(
+        self: &Self,
+        request: HttpRequest,
+        params: HttpRequestParams,
+    ) -> BoxFuture<'_, Result<HttpResponse, HttpError>>;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(HttpRequest, HttpRequestParams) -> BoxFuture<'static, Result<HttpResponse, HttpError>>
+        + Send
+        + Sync,
+{
+    fn call(
+        self: &Self,
+        request: HttpRequest,
+        params: HttpRequestParams,
+    ) -> BoxFuture<'_, Result<HttpResponse, HttpError>> {
+        self(request, params)
+    }
+}
+
+type SyncHandler =
+    dyn Fn(HttpRequest, HttpRequestParams) -> Result<HttpResponse, HttpError> + Send + Sync;
+
+/// Plain handlers run inline on the runtime, so they must not block; anything
+/// doing I/O should be registered as an async handler instead.
+#[derive(Clone)]
+enum HandlerInner {
+    Sync(Arc<SyncHandler>),
+    Async(Arc<dyn Handler>),
+}
+
+impl Debug for HandlerInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandlerInner::Sync(_) => f.write_str("Sync(..)"),
+            HandlerInner::Async(_) => f.write_str("Async(..)"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HttpRegexEndpoint {
+    method: HttpMethod,
+    /// What to call the route when building URLs for it.
+    name: Option<String>,
+    /// The route as registered, e.g. `/files/:filename`.
+    pattern: String,
+    /// The parsed pattern and the regex matching it, or why the pattern is
+    /// invalid. Routers refuse to be built from invalid endpoints.
+    compiled: Result<(Vec<Part>, Regex), String>,
+    handler: HandlerInner,
+    max_body_size: Option<usize>,
+}
+
+impl HttpRegexEndpoint {
+    /// An endpoint calling a plain function, whose arguments are extractors.
+    pub fn new<Args>(
+        method: HttpMethod,
+        path_str: impl AsRef<str>,
+        handler: impl HandlerFn<Args>,
+    ) -> Self {
+        let handler =
+            move |req: HttpRequest, params: HttpRequestParams| handler.handle(req, params);
+
+        Self::with_handler(method, path_str, HandlerInner::Sync(Arc::new(handler)))
+    }
+
+    /// An endpoint calling an async function, whose arguments are
+    /// extractors.
+    pub fn new_async<Args>(
+        method: HttpMethod,
+        path_str: impl AsRef<str>,
+        handler: impl AsyncHandlerFn<Args>,
+    ) -> Self {
+        let handler =
+            move |req: HttpRequest, params: HttpRequestParams| handler.handle(req, params);
+
+        Self::from_handler(method, path_str, handler)
+    }
+
+    pub fn from_handler(
+        method: HttpMethod,
+        path_str: impl AsRef<str>,
+        handler: impl Handler + 'static,
+    ) -> Self {
+        Self::with_handler(method, path_str, HandlerInner::Async(Arc::new(handler)))
+    }
+
+    fn with_handler(method: HttpMethod, path_str: impl AsRef<str>, handler: HandlerInner) -> Self {
+        let path_str = path_str.as_ref();
+
+        let compiled = pattern::parse(path_str).and_then(|parts| {
+            let path = Regex::from_str(&pattern::to_regex(&parts))
+                .map_err(|err| format!("Invalid route pattern {:?}: {}", path_str, err))?;
+
+            Ok((parts, path))
+        });
+
+        HttpRegexEndpoint {
+            method,
+            name: None,
+            pattern: path_str.to_string(),
+            compiled,
+            handler,
+            max_body_size: None,
+        }
+    }
+
+    fn compiled(self: &Self) -> &(Vec<Part>, Regex) {
+        self.compiled
+            .as_ref()
+            .expect("Routers are only built from valid endpoints")
+    }
+
+    fn parts(self: &Self) -> &[Part] {
+        &self.compiled().0
+    }
+
+    fn path(self: &Self) -> &Regex {
+        &self.compiled().1
+    }
+
+    /// Whether the endpoint's pattern matches `path`, constraints included.
+    fn matches(self: &Self, path: &str) -> bool {
+        let Some(captures) = self.path().captures(path) else {
+            return false;
+        };
+
+        self.parts().iter().all(|part| match &part.segment {
+            Segment::Param {
+                name,
+                constraint: Some(constraint),
+            } => match captures.name(name) {
+                Some(value) => constraint.accepts(value.as_str()),
+                None => true,
+            },
+            _ => true,
+        })
+    }
+
+    /// Names the route, so that `url_for` can build URLs leading to it.
+    pub fn name(mut self: Self, name: impl AsRef<str>) -> Self {
+        self.name = Some(name.as_ref().to_string());
+
+        self
+    }
+
+    /// Rejects requests announcing a larger `Content-Length` with 413.
+    pub fn max_body_size(mut self: Self, max_body_size: usize) -> Self {
+        self.max_body_size = Some(max_body_size);
+
+        self
+    }
+}
+
+/// Why a set of endpoints cannot be made into a router.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteError {
+    InvalidPattern {
+        reason: String,
+    },
+    /// Two endpoints handle the same method on the same paths.
+    Duplicate {
+        method: HttpMethod,
+        pattern: String,
+        existing: String,
+    },
+    /// An endpoint registered earlier matches every path this one does, so
+    /// it would never be reached.
+    Shadowed {
+        method: HttpMethod,
+        pattern: String,
+        shadowed_by: String,
+    },
+    /// Two endpoints have the same name.
+    DuplicateName {
+        name: String,
+    },
+    /// Two routers are mounted under the same prefix.
+    MountConflict {
+        prefix: String,
+    },
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::InvalidPattern { reason } => f.write_str(reason),
+            RouteError::Duplicate {
+                method,
+                pattern,
+                existing,
+            } => write!(
+                f,
+                "{} {} conflicts with {} {}, registered before it",
+                method, pattern, method, existing
+            ),
+            RouteError::Shadowed {
+                method,
+                pattern,
+                shadowed_by,
+            } => write!(
+                f,
+                "{} {} can never match, as {} {}, registered before it, matches every path it does",
+                method, pattern, method, shadowed_by
+            ),
+            RouteError::DuplicateName { name } => {
+                write!(f, "More than one route is named {:?}", name)
+            }
+            RouteError::MountConflict { prefix } => {
+                write!(f, "Another router is already mounted under {}", prefix)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+/// Checks that every endpoint has a valid pattern and that no two of them
+/// handle the same method on the same paths. When `first_match_wins`, as
+/// with `RegexRouter`, endpoints made unreachable by an earlier one are
+/// rejected too.
+fn validate_endpoints(
+    endpoints: &[HttpRegexEndpoint],
+    first_match_wins: bool,
+) -> Result<(), RouteError> {
+    for endpoint in endpoints {
+        if let Err(reason) = &endpoint.compiled {
+            return Err(RouteError::InvalidPattern {
+                reason: reason.clone(),
+            });
+        }
+    }
+
+    let variants = endpoints
+        .iter()
+        .map(|endpoint| pattern::expand(endpoint.parts()))
+        .collect::<Vec<_>>();
+
+    for (i, endpoint) in endpoints.iter().enumerate() {
+        for (j, earlier) in endpoints[..i].iter().enumerate() {
+            if earlier.method != endpoint.method {
+                continue;
+            }
+
+            let duplicate = variants[i]
+                .iter()
+                .any(|a| variants[j].iter().any(|b| pattern::same_shape(a, b)));
+
+            if duplicate {
+                return Err(RouteError::Duplicate {
+                    method: endpoint.method.clone(),
+                    pattern: endpoint.pattern.clone(),
+                    existing: earlier.pattern.clone(),
+                });
+            }
+
+            let shadowed = variants[i]
+                .iter()
+                .all(|a| variants[j].iter().any(|b| pattern::covers(b, a)));
+
+            if first_match_wins && shadowed {
+                return Err(RouteError::Shadowed {
+                    method: endpoint.method.clone(),
+                    pattern: endpoint.pattern.clone(),
+                    shadowed_by: earlier.pattern.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The patterns of the endpoints that were given a name.
+fn name_endpoints(endpoints: &[HttpRegexEndpoint]) -> Result<RouteNames, RouteError> {
+    let mut names = RouteNames::default();
+
+    for endpoint in endpoints {
+        if let Some(name) = &endpoint.name {
+            names.insert(name, endpoint.parts().to_vec())?;
+        }
+    }
+
+    Ok(names)
+}
+
+/// What a router hands down to the handlers it dispatches to.
+#[derive(Clone, Debug, Default)]
+pub struct HandlerContext {
+    names: Arc<RouteNames>,
+    state: Option<SharedState>,
+    renderer: Option<Arc<dyn ErrorRenderer>>,
+}
+
+impl HandlerContext {
+    fn new(names: Arc<RouteNames>) -> Self {
+        HandlerContext {
+            names,
+            ..HandlerContext::default()
+        }
+    }
+
+    /// Gives `req` this router's state and error renderer, if it has any, in
+    /// place of those of any router it is nested in. Route names are only
+    /// given to requests without any, as the outermost router knows them all.
+    fn prepare(self: &Self, req: &mut HttpRequest) {
+        if self.state.is_some() {
+            req.state = self.state.clone();
+        }
+
+        if self.renderer.is_some() {
+            req.error_renderer = self.renderer.clone();
+        }
+
+        if req.route_names.is_none() {
+            req.route_names = Some(self.names.clone());
+        }
+    }
+}
+
+/// Configures what a router hands down to its handlers.
+pub trait ConfigureHandlers: Sized {
+    fn handler_context(self: &mut Self) -> &mut HandlerContext;
+
+    /// Makes `state` available to every handler this router dispatches to,
+    /// through `HttpRequest::state`. Routers nested in this one keep their
+    /// own state, if they were given any.
+    fn with_state<T: Any + Send + Sync>(mut self: Self, state: T) -> Self {
+        self.handler_context().state = Some(SharedState::new(state));
+
+        self
+    }
+
+    /// Renders the errors handlers return with `renderer` instead of as
+    /// plain text. Routers nested in this one keep their own renderer, if
+    /// they were given one.
+    fn with_error_renderer(mut self: Self, renderer: impl ErrorRenderer + 'static) -> Self {
+        self.handler_context().renderer = Some(Arc::new(renderer));
+
+        self
+    }
+}
+
+/// The routers endpoints can be served with, by the names the
+/// `routing.router` setting takes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RouterKind {
+    Trie,
+    Regex,
+}
+
+impl RouterKind {
+    pub fn build(
+        self: Self,
+        endpoints: Vec<HttpRegexEndpoint>,
+    ) -> Result<Box<dyn HttpRouter>, RouteError> {
+        Ok(match self {
+            RouterKind::Trie => Box::new(TrieRouter::new(endpoints)?),
+            RouterKind::Regex => Box::new(RegexRouter::new(endpoints)?),
+        })
+    }
+}
+
+impl FromStr for RouterKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind.to_lowercase().as_str() {
+            "trie" => Ok(RouterKind::Trie),
+            "regex" => Ok(RouterKind::Regex),
+            _ => Err(format!("Unknown router {:?}, expected trie or regex", kind)),
+        }
+    }
+}
+
+/// Tries endpoints in the order they were registered, taking the first one
+/// whose pattern matches.
+#[derive(Clone, Debug)]
+pub struct RegexRouter {
+    endpoints: Vec<HttpRegexEndpoint>,
+    context: HandlerContext,
+}
+
+impl RegexRouter {
+    pub fn new(endpoints: Vec<HttpRegexEndpoint>) -> Result<Self, RouteError> {
+        validate_endpoints(&endpoints, true)?;
+        let names = name_endpoints(&endpoints)?;
+
+        Ok(RegexRouter {
+            endpoints,
+            context: HandlerContext::new(Arc::new(names)),
+        })
+    }
+
+    pub fn extract_params(self: &Self, path: &Regex, target: &str) -> HashMap<String, String> {
+        let mut params = HashMap::<String, String>::new();
+        let captures = path.captures(target);
+        if let Some(captures) = captures {
+            captures
+                .iter()
+                .zip(path.capture_names())
+                .filter_map(|(capture, name)| Option::zip(name, capture))
+                .for_each(|(name, capture)| {
+                    params.insert(name.to_string(), pattern::percent_decode(capture.as_str()));
+                });
+        }
+
+        params
+    }
+}
+
+/// What a router does with a request, given the endpoints whose path matches.
+enum Route<'a, E> {
+    Endpoint(&'a E),
+    /// Answer `OPTIONS` with the methods the path allows.
+    Options(String),
+    NotAllowed(String),
+    NotImplemented,
+    NotFound,
+}
+
+/// The `Allow` header for a path served by `methods`: `HEAD` comes with
+/// every `GET`, and `OPTIONS` is always answered.
+fn allow_header<'a>(methods: impl IntoIterator<Item = &'a HttpMethod>) -> String {
+    let mut allowed = Vec::<&HttpMethod>::new();
+    for method in methods {
+        if !allowed.contains(&method) {
+            allowed.push(method);
+        }
+    }
+
+    if allowed.contains(&&HttpMethod::GET) && !allowed.contains(&&HttpMethod::HEAD) {
+        allowed.push(&HttpMethod::HEAD);
+    }
+
+    if !allowed.contains(&&HttpMethod::OPTIONS) {
+        allowed.push(&HttpMethod::OPTIONS);
+    }
+
+    allowed
+        .iter()
+        .map(|method| method.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Picks the endpoint for `method` out of those matching the request's path.
+/// `method_is_known` tells whether any endpoint, on any path, handles it.
+fn resolve_route<'a, E>(
+    method: &HttpMethod,
+    candidates: &[(&HttpMethod, &'a E)],
+    method_is_known: bool,
+) -> Route<'a, E> {
+    let find = |wanted: &HttpMethod| {
+        candidates
+            .iter()
+            .find(|(method, _)| *method == wanted)
+            .map(|(_, endpoint)| *endpoint)
+    };
+
+    let unknown_method = matches!(method, HttpMethod::Other(_)) && !method_is_known;
+
+    if candidates.is_empty() {
+        return match unknown_method {
+            true => Route::NotImplemented,
+            false => Route::NotFound,
+        };
+    }
+
+    if let Some(endpoint) = find(method) {
+        return Route::Endpoint(endpoint);
+    }
+
+    let allow = || allow_header(candidates.iter().map(|(method, _)| *method));
+
+    match method {
+        // The connection leaves out the body once the response is written.
+        HttpMethod::HEAD => match find(&HttpMethod::GET) {
+            Some(endpoint) => Route::Endpoint(endpoint),
+            None => Route::NotAllowed(allow()),
+        },
+        HttpMethod::OPTIONS => Route::Options(allow()),
+        _ if unknown_method => Route::NotImplemented,
+        _ => Route::NotAllowed(allow()),
+    }
+}
+
+impl<E> Route<'_, E> {
+    /// The answer for every route but an endpoint: either an automatic
+    /// `OPTIONS` response or the error to reject the request with.
+    fn response(self: Self) -> Option<Result<HttpResponse, HttpError>> {
+        let err = match self {
+            Route::Endpoint(_) => return None,
+            Route::Options(allow) => {
+                return Some(Ok(HttpResponseBuilder::default()
+                    .status(204, Some("No Content"))
+                    .header("Allow", allow)
+                    .build()))
+            }
+            Route::NotAllowed(allow) => HttpError::status(405).header("Allow", allow),
+            Route::NotImplemented => HttpError::status(501),
+            Route::NotFound => HttpError::status(404),
+        };
+
+        Some(Err(err))
+    }
+}
+
+/// Logs an error and renders it with `renderer`, or as plain text if there
+/// is none.
+fn render_error(renderer: Option<&dyn ErrorRenderer>, err: HttpError) -> HttpResponse {
+    err.log();
+
+    match renderer {
+        Some(renderer) => renderer.render(&err),
+        None => PlainText.render(&err),
+    }
+}
+
+/// Runs an endpoint's handler, turning any error into its response with the
+/// request's error renderer.
+async fn call_endpoint(
+    endpoint: &HttpRegexEndpoint,
+    req: HttpRequest,
+    params: HttpRequestParams,
+) -> HttpResponse {
+    let renderer = req.error_renderer.clone();
+    let result = match &endpoint.handler {
+        HandlerInner::Sync(handler) => handler(req, params),
+        HandlerInner::Async(handler) => handler.call(req, params).await,
+    };
+
+    result.unwrap_or_else(|err| render_error(renderer.as_deref(), err))
+}
+
+/// Rejects requests announcing a larger body than the endpoint accepts, and
+/// passes the endpoint's limit on for bodies that do not announce their size.
+fn check_body_size(
+    endpoint: &HttpRegexEndpoint,
+    req: &HttpRequest,
+) -> Result<Option<usize>, HttpError> {
+    let content_length = req
+        .headers
+        .get("content-length")
+        .and_then(|content_length| content_length.parse::<usize>().ok());
+
+    if let (Some(max_body_size), Some(content_length)) = (endpoint.max_body_size, content_length) {
+        if content_length > max_body_size {
+            return Err(HttpError::status(413));
+        }
+    }
+
+    Ok(endpoint.max_body_size)
+}
+
+impl RegexRouter {
+    fn route(self: &Self, req: &HttpRequest) -> Route<'_, HttpRegexEndpoint> {
+        // `OPTIONS *` asks about the server as a whole.
+        if req.target == HttpRequestTarget::Asterisk {
+            return Route::Options(allow_header(
+                self.endpoints.iter().map(|endpoint| &endpoint.method),
+            ));
+        }
+
+        // `CONNECT host:port` names a server to tunnel to rather than a path.
+        if let HttpRequestTarget::Authority(_) = req.target {
+            return Route::NotFound;
+        }
+
+        let path = req.target.path();
+        let candidates = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.matches(path))
+            .map(|endpoint| (&endpoint.method, endpoint))
+            .collect::<Vec<_>>();
+
+        let method_is_known = self
+            .endpoints
+            .iter()
+            .any(|endpoint| endpoint.method == req.method);
+
+        resolve_route(&req.method, &candidates, method_is_known)
+    }
+}
+
+impl HttpRouter for RegexRouter {
+    fn dispatch(self: &Self, mut req: HttpRequest) -> BoxFuture<'_, HttpResponse> {
+        Box::pin(async move {
+            let endpoint = match self.route(&req) {
+                Route::Endpoint(endpoint) => endpoint,
+                route => match route.response().unwrap() {
+                    Ok(res) => return res,
+                    Err(err) => return self.reject(req, err).await,
+                },
+            };
+
+            self.context.prepare(&mut req);
+            let params = self.extract_params(endpoint.path(), req.target.path());
+
+            call_endpoint(endpoint, req, params).await
+        })
+    }
+
+    fn precheck(self: &Self, req: &HttpRequest) -> Result<Option<usize>, HttpError> {
+        match self.route(req) {
+            Route::Endpoint(endpoint) => check_body_size(endpoint, req),
+            // Automatic OPTIONS answers do not care about the body.
+            route => route.response().unwrap().map(|_| None),
+        }
+    }
+
+    fn reject(self: &Self, mut req: HttpRequest, err: HttpError) -> BoxFuture<'_, HttpResponse> {
+        self.context.prepare(&mut req);
+
+        Box::pin(async move { render_error(req.error_renderer.as_deref(), err) })
+    }
+
+    fn route_names(self: &Self) -> Arc<RouteNames> {
+        self.context.names.clone()
+    }
+}
+
+impl ConfigureHandlers for RegexRouter {
+    fn handler_context(self: &mut Self) -> &mut HandlerContext {
+        &mut self.context
+    }
+}
+
+/// Matches a request's host name, either exactly or, for patterns like
+/// `*.preview.local`, any subdomain of the given suffix.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HostPattern {
+    Exact(String),
+    Wildcard(String),
+}
+
+impl HostPattern {
+    pub fn new(pattern: impl AsRef<str>) -> Self {
+        let pattern = pattern.as_ref().to_lowercase();
+
+        match pattern.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') => HostPattern::Wildcard(suffix.to_string()),
+            _ => HostPattern::Exact(pattern),
+        }
+    }
+
+    pub fn matches(self: &Self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(name) => name == host,
+            HostPattern::Wildcard(suffix) => {
+                host.len() > suffix.len() && host.ends_with(suffix.as_str())
+            }
+        }
+    }
+}
+
+/// Dispatches to a different router depending on the request's host name.
+///
+/// Exact host names take precedence over wildcards, and longer wildcards over
+/// shorter ones; requests matching no host go to the default router.
+#[derive(Clone, Debug)]
+pub struct VirtualHostRouter<R: HttpRouter = RegexRouter> {
+    hosts: Vec<(HostPattern, R)>,
+    default: R,
+}
+
+impl<R: HttpRouter> VirtualHostRouter<R> {
+    pub fn new(default: R) -> Self {
+        VirtualHostRouter {
+            hosts: vec![],
+            default,
+        }
+    }
+
+    pub fn host(mut self: Self, pattern: impl AsRef<str>, router: R) -> Self {
+        self.hosts.push((HostPattern::new(pattern), router));
+        self.hosts.sort_by_key(|(pattern, _)| match pattern {
+            HostPattern::Exact(_) => (0, 0),
+            HostPattern::Wildcard(suffix) => (1, usize::MAX - suffix.len()),
+        });
+
+        self
+    }
+
+    fn route(self: &Self, host: Option<&str>) -> &R {
+        host.and_then(|host| {
+            self.hosts
+                .iter()
+                .find(|(pattern, _)| pattern.matches(host))
+                .map(|(_, router)| router)
+        })
+        .unwrap_or(&self.default)
+    }
+}
+
+impl<R: HttpRouter> HttpRouter for VirtualHostRouter<R> {
+    fn dispatch(self: &Self, req: HttpRequest) -> BoxFuture<'_, HttpResponse> {
+        let host = req.host();
+
+        self.route(host.as_deref()).dispatch(req)
+    }
+
+    fn precheck(self: &Self, req: &HttpRequest) -> Result<Option<usize>, HttpError> {
+        let host = req.host();
+
+        self.route(host.as_deref()).precheck(req)
+    }
+
+    fn reject(self: &Self, req: HttpRequest, err: HttpError) -> BoxFuture<'_, HttpResponse> {
+        let host = req.host();
+
+        self.route(host.as_deref()).reject(req, err)
+    }
+
+    /// URLs are built from the default router's routes, as they carry no
+    /// host name.
+    fn route_names(self: &Self) -> Arc<RouteNames> {
+        self.default.route_names()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(method: HttpMethod, pattern: &str) -> HttpRegexEndpoint {
+        HttpRegexEndpoint::new(method, pattern, || ())
+    }
+
+    fn get(pattern: &str) -> HttpRegexEndpoint {
+        endpoint(HttpMethod::GET, pattern)
+    }
+
+    /// Builds both routers from the same endpoints, returning why each
+    /// refused them, if it did.
+    fn errors(patterns: &[&str]) -> (Option<RouteError>, Option<RouteError>) {
+        let endpoints = || patterns.iter().map(|pattern| get(pattern)).collect();
+
+        (
+            RegexRouter::new(endpoints()).err(),
+            TrieRouter::new(endpoints()).err(),
+        )
+    }
+
+    #[test]
+    fn accepts_distinct_routes() {
+        assert_eq!(
+            errors(&["/", "/a", "/a/:x", "/b/*rest", "/a/:x/c"]),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for pattern in ["a", "/a/:1x", "/a/:x(", "/a/*rest/b"] {
+            let (regex, trie) = errors(&[pattern]);
+
+            assert!(
+                matches!(regex, Some(RouteError::InvalidPattern { .. })),
+                "{}",
+                pattern
+            );
+            assert!(
+                matches!(trie, Some(RouteError::InvalidPattern { .. })),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_routes_differing_only_in_param_names() {
+        let (regex, trie) = errors(&["/a/:x", "/a/:y"]);
+        let duplicate = Some(RouteError::Duplicate {
+            method: HttpMethod::GET,
+            pattern: "/a/:y".into(),
+            existing: "/a/:x".into(),
+        });
+
+        assert_eq!(regex, duplicate);
+        assert_eq!(trie, duplicate);
+    }
+
+    #[test]
+    fn rejects_optional_segments_overlapping_a_route() {
+        let (regex, trie) = errors(&["/a/:x?", "/a"]);
+
+        assert!(matches!(regex, Some(RouteError::Duplicate { .. })));
+        assert!(matches!(trie, Some(RouteError::Duplicate { .. })));
+    }
+
+    #[test]
+    fn rejects_shadowed_routes_when_the_first_match_wins() {
+        let (regex, trie) = errors(&["/a/:x", "/a/b"]);
+
+        assert_eq!(
+            regex,
+            Some(RouteError::Shadowed {
+                method: HttpMethod::GET,
+                pattern: "/a/b".into(),
+                shadowed_by: "/a/:x".into(),
+            })
+        );
+        // The trie prefers static segments, so both stay reachable.
+        assert_eq!(trie, None);
+    }
+
+    #[test]
+    fn rejects_routes_behind_a_catch_all() {
+        let (regex, _) = errors(&["/a/*rest", "/a/b/:c"]);
+
+        assert!(matches!(regex, Some(RouteError::Shadowed { .. })));
+        assert_eq!(errors(&["/a/b/:c", "/a/*rest"]), (None, None));
+    }
+
+    #[test]
+    fn keeps_routes_whose_constraint_narrows_them() {
+        let (regex, _) = errors(&["/a/:id<u64>", "/a/:name"]);
+        assert_eq!(regex, None);
+
+        let (regex, _) = errors(&["/a/:name", "/a/:id<u64>"]);
+        assert!(matches!(regex, Some(RouteError::Shadowed { .. })));
+    }
+
+    #[test]
+    fn allows_the_same_path_for_different_methods() {
+        let endpoints = || vec![get("/a/:x"), endpoint(HttpMethod::POST, "/a/:y")];
+
+        assert!(RegexRouter::new(endpoints()).is_ok());
+        assert!(TrieRouter::new(endpoints()).is_ok());
+    }
+
+    #[test]
+    fn rejects_one_name_for_different_paths() {
+        let endpoints = vec![get("/a").name("a"), get("/b").name("a")];
+
+        assert_eq!(
+            TrieRouter::new(endpoints).err(),
+            Some(RouteError::DuplicateName { name: "a".into() })
+        );
+
+        let endpoints = vec![
+            get("/a").name("a"),
+            endpoint(HttpMethod::POST, "/a").name("a"),
+        ];
+        assert!(TrieRouter::new(endpoints).is_ok());
+    }
+
+    #[tokio::test]
+    async fn does_not_route_authority_form_targets() {
+        let endpoints = || vec![get("/"), get("/*rest"), endpoint(HttpMethod::CONNECT, "/")];
+        let routers: [Box<dyn HttpRouter>; 2] = [
+            Box::new(RegexRouter::new(endpoints()).unwrap()),
+            Box::new(TrieRouter::new(endpoints()).unwrap()),
+        ];
+
+        for router in routers {
+            let mut req = testing::request("GET", "/");
+            req.method = HttpMethod::CONNECT;
+            req.target = HttpRequestTarget::Authority("example.com:443".into());
+
+            assert_eq!(router.dispatch(req).await.status_code, 404);
+        }
+    }
+
+    #[test]
+    fn builds_urls_for_named_routes() {
+        let router = RegexRouter::new(vec![get("/users/:id").name("user")]).unwrap();
+        let params = HashMap::from([("id".to_string(), "42".to_string())]);
+
+        assert_eq!(router.url_for("user", &params), Ok("/users/42".to_string()));
+        assert!(router.url_for("missing", &params).is_err());
+    }
+
+    #[tokio::test]
+    async fn routes_by_host_exact_names_first() {
+        let site = |name: &'static str| {
+            RegexRouter::new(vec![HttpRegexEndpoint::new(
+                HttpMethod::GET,
+                "/",
+                move || name,
+            )])
+            .unwrap()
+        };
+
+        let router = VirtualHostRouter::new(site("default"))
+            .host("*.local", site("local"))
+            .host("*.preview.local", site("preview"))
+            .host("a.preview.local", site("a"));
+
+        for (host, expected) in [
+            ("a.preview.local:8080", "a"),
+            ("b.preview.local", "preview"),
+            ("b.local", "local"),
+            ("local", "default"),
+            ("example.com", "default"),
+        ] {
+            let mut req = testing::request("GET", "/");
+            req.headers.insert("host", host);
+
+            let body = router.dispatch(req).await.body.unwrap();
+            assert_eq!(body, expected.as_bytes(), "{}", host);
+        }
+    }
+}
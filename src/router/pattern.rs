@@ -0,0 +1,582 @@
+//! Route patterns like `/files/:filename`, shared by both routers.
+//!
+//! A pattern is made of `/`-separated segments, each of which is one of:
+//!
+//! - a literal, matched exactly: `/files`
+//! - a param, matching any single non-empty segment: `/:name`
+//! - a constrained param, matching only segments that fit a regex or parse
+//!   as an integer type: `/:id(\d+)`, `/:id<u64>`
+//! - a catch-all, matching the rest of the path, slashes included: `/*rest`
+//!
+//! Any segment can be made optional with a trailing `?`, so that
+//! `/posts/:page?` matches both `/posts` and `/posts/2`. Catch-alls may only
+//! end a pattern.
+
+use std::fmt::{self, Debug};
+
+use regex::Regex;
+
+/// An integer type a param has to parse as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamType {
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
+    I8,
+    I16,
+    I32,
+    I64,
+    Isize,
+}
+
+impl ParamType {
+    fn from_name(name: &str) -> Option<Self> {
+        let param_type = match name {
+            "u8" => ParamType::U8,
+            "u16" => ParamType::U16,
+            "u32" => ParamType::U32,
+            "u64" => ParamType::U64,
+            "usize" => ParamType::Usize,
+            "i8" => ParamType::I8,
+            "i16" => ParamType::I16,
+            "i32" => ParamType::I32,
+            "i64" => ParamType::I64,
+            "isize" => ParamType::Isize,
+            _ => return None,
+        };
+
+        Some(param_type)
+    }
+
+    fn accepts(self: &Self, value: &str) -> bool {
+        // `parse` lets a leading `+` through, which has no place in a path.
+        if value.starts_with('+') {
+            return false;
+        }
+
+        match self {
+            ParamType::U8 => value.parse::<u8>().is_ok(),
+            ParamType::U16 => value.parse::<u16>().is_ok(),
+            ParamType::U32 => value.parse::<u32>().is_ok(),
+            ParamType::U64 => value.parse::<u64>().is_ok(),
+            ParamType::Usize => value.parse::<usize>().is_ok(),
+            ParamType::I8 => value.parse::<i8>().is_ok(),
+            ParamType::I16 => value.parse::<i16>().is_ok(),
+            ParamType::I32 => value.parse::<i32>().is_ok(),
+            ParamType::I64 => value.parse::<i64>().is_ok(),
+            ParamType::Isize => value.parse::<isize>().is_ok(),
+        }
+    }
+}
+
+/// What a constrained param's segment has to look like.
+#[derive(Clone)]
+pub enum Constraint {
+    /// The whole segment must match the regex.
+    Regex(Regex),
+    Type(ParamType),
+}
+
+impl Constraint {
+    pub fn accepts(self: &Self, value: &str) -> bool {
+        match self {
+            Constraint::Regex(regex) => regex.is_match(value),
+            Constraint::Type(param_type) => param_type.accepts(value),
+        }
+    }
+}
+
+impl PartialEq for Constraint {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Constraint::Regex(a), Constraint::Regex(b)) => a.as_str() == b.as_str(),
+            (Constraint::Type(a), Constraint::Type(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Debug for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constraint::Regex(regex) => write!(f, "Regex({:?})", regex.as_str()),
+            Constraint::Type(param_type) => write!(f, "Type({:?})", param_type),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    Literal(String),
+    Param {
+        name: String,
+        constraint: Option<Constraint>,
+    },
+    CatchAll(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Part {
+    pub segment: Segment,
+    pub optional: bool,
+}
+
+/// How many segments of a pattern may be optional. Each one doubles the
+/// concrete forms the pattern expands to.
+const MAX_OPTIONAL_SEGMENTS: usize = 8;
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn parse_param(piece: &str, pattern: &str) -> Result<Segment, String> {
+    let name_end = piece.find(|c| !is_name_char(c)).unwrap_or(piece.len());
+    let (name, rest) = piece.split_at(name_end);
+
+    if name.is_empty() {
+        return Err(format!("Parameters must be named in {:?}", pattern));
+    }
+
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(format!(
+            "Parameter names cannot start with a digit, got :{} in {:?}",
+            name, pattern
+        ));
+    }
+
+    let constraint = if let Some(regex) = rest.strip_prefix('(') {
+        let regex = regex
+            .strip_suffix(')')
+            .ok_or_else(|| format!("Unclosed constraint on :{} in {:?}", name, pattern))?;
+
+        let regex = Regex::new(&format!("^(?:{})$", regex))
+            .map_err(|err| format!("Invalid constraint on :{} in {:?}: {}", name, pattern, err))?;
+
+        Some(Constraint::Regex(regex))
+    } else if let Some(type_name) = rest.strip_prefix('<') {
+        let param_type = type_name
+            .strip_suffix('>')
+            .and_then(ParamType::from_name)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown type constraint on :{} in {:?}, expected an integer type like <u64>",
+                    name, pattern
+                )
+            })?;
+
+        Some(Constraint::Type(param_type))
+    } else if rest.is_empty() {
+        None
+    } else {
+        return Err(format!(
+            "Unexpected {:?} after :{} in {:?}",
+            rest, name, pattern
+        ));
+    };
+
+    Ok(Segment::Param {
+        name: name.to_string(),
+        constraint,
+    })
+}
+
+/// Splits a route pattern into its segments.
+pub fn parse(pattern: &str) -> Result<Vec<Part>, String> {
+    let Some(path) = pattern.strip_prefix('/') else {
+        return Err(format!("Paths must start with a '/', got {:?}", pattern));
+    };
+
+    let pieces = path.split('/').collect::<Vec<_>>();
+    let mut parts = Vec::with_capacity(pieces.len());
+    let mut names = Vec::new();
+
+    for (i, piece) in pieces.iter().enumerate() {
+        let (piece, optional) = match piece.strip_suffix('?') {
+            Some(piece) => (piece, true),
+            None => (*piece, false),
+        };
+
+        let segment = if let Some(param) = piece.strip_prefix(':') {
+            parse_param(param, pattern)?
+        } else if let Some(name) = piece.strip_prefix('*') {
+            if name.is_empty()
+                || !name.chars().all(is_name_char)
+                || name.starts_with(|c: char| c.is_ascii_digit())
+            {
+                return Err(format!(
+                    "Invalid catch-all name {:?} in {:?}",
+                    name, pattern
+                ));
+            }
+
+            if i != pieces.len() - 1 {
+                return Err(format!("Catch-alls must end the path in {:?}", pattern));
+            }
+
+            Segment::CatchAll(name.to_string())
+        } else {
+            Segment::Literal(piece.to_string())
+        };
+
+        if let Segment::Param { name, .. } | Segment::CatchAll(name) = &segment {
+            if names.contains(name) {
+                return Err(format!(
+                    "Parameter {:?} appears twice in {:?}",
+                    name, pattern
+                ));
+            }
+
+            names.push(name.clone());
+        }
+
+        parts.push(Part { segment, optional });
+    }
+
+    if parts.iter().filter(|part| part.optional).count() > MAX_OPTIONAL_SEGMENTS {
+        return Err(format!(
+            "At most {} segments may be optional in {:?}",
+            MAX_OPTIONAL_SEGMENTS, pattern
+        ));
+    }
+
+    Ok(parts)
+}
+
+/// Every concrete form of a pattern, with each optional segment either
+/// present or left out.
+pub fn expand(parts: &[Part]) -> Vec<Vec<Segment>> {
+    let mut variants = vec![vec![]];
+
+    for part in parts {
+        let with = variants
+            .iter()
+            .map(|variant| {
+                let mut variant = variant.clone();
+                variant.push(part.segment.clone());
+                variant
+            })
+            .collect::<Vec<_>>();
+
+        if !part.optional {
+            variants.clear();
+        }
+
+        variants.extend(with);
+    }
+
+    // Leaving every segment out leaves the root path.
+    for variant in variants.iter_mut() {
+        if variant.is_empty() {
+            variant.push(Segment::Literal("".into()));
+        }
+    }
+
+    variants
+}
+
+/// The regex matching the same paths as a pattern, capturing each param
+/// and catch-all in a group of the same name. Constraints are not part of
+/// it and have to be checked separately.
+pub fn to_regex(parts: &[Part]) -> String {
+    let mut regex = String::from("^");
+
+    for part in parts {
+        let segment = match &part.segment {
+            Segment::Literal(literal) => regex::escape(literal),
+            Segment::Param { name, .. } => format!("(?<{}>[^/]+)", name),
+            Segment::CatchAll(name) => format!("(?<{}>.+)", name),
+        };
+
+        match part.optional {
+            true => regex.push_str(&format!("(?:/{})?", segment)),
+            false => regex.push_str(&format!("/{}", segment)),
+        }
+    }
+
+    if parts.iter().all(|part| part.optional) {
+        regex.push_str("/?");
+    }
+
+    regex.push('$');
+    regex
+}
+
+/// Whether two concrete patterns match exactly the same paths, whatever
+/// their params are called.
+pub fn same_shape(a: &[Segment], b: &[Segment]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|pair| match pair {
+            (Segment::Literal(a), Segment::Literal(b)) => a == b,
+            (Segment::Param { constraint: a, .. }, Segment::Param { constraint: b, .. }) => a == b,
+            (Segment::CatchAll(_), Segment::CatchAll(_)) => true,
+            _ => false,
+        })
+}
+
+fn segment_covers(a: &Segment, b: &Segment) -> bool {
+    match (a, b) {
+        (Segment::Literal(a), Segment::Literal(b)) => a == b,
+        (Segment::Param { constraint, .. }, Segment::Literal(b)) => match constraint {
+            _ if b.is_empty() => false,
+            Some(constraint) => constraint.accepts(b),
+            None => true,
+        },
+        (
+            Segment::Param {
+                constraint: None, ..
+            },
+            Segment::Param { .. },
+        ) => true,
+        (Segment::Param { constraint: a, .. }, Segment::Param { constraint: b, .. }) => a == b,
+        _ => false,
+    }
+}
+
+/// Whether every path concrete pattern `b` matches is also matched by `a`.
+pub fn covers(a: &[Segment], b: &[Segment]) -> bool {
+    match (a.split_first(), b.split_first()) {
+        (None, None) => true,
+        // A catch-all takes whatever is left, as long as that is not empty.
+        (Some((Segment::CatchAll(_), _)), Some(_)) => b != [Segment::Literal("".into())],
+        (Some((a, a_rest)), Some((b, b_rest))) => segment_covers(a, b) && covers(a_rest, b_rest),
+        _ => false,
+    }
+}
+
+/// Decodes the `%XX` escapes in a param's value. Anything that is not a
+/// valid escape is kept as it is.
+pub fn percent_decode(value: &str) -> String {
+    if !value.contains('%') {
+        return value.to_string();
+    }
+
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit));
+
+        match escape {
+            Some(hex) => {
+                let hex = std::str::from_utf8(hex).unwrap();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Splits a request path into segments the same way patterns are.
+pub fn split_path(path: &str) -> Vec<&str> {
+    path.strip_prefix('/').unwrap_or(path).split('/').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(literal: &str) -> Segment {
+        Segment::Literal(literal.into())
+    }
+
+    fn param(name: &str) -> Segment {
+        Segment::Param {
+            name: name.into(),
+            constraint: None,
+        }
+    }
+
+    fn regex(pattern: &str) -> Regex {
+        Regex::new(&to_regex(&parse(pattern).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn parses_segments() {
+        let parts = parse("/files/:name/*rest").unwrap();
+        let segments = parts.iter().map(|part| &part.segment).collect::<Vec<_>>();
+
+        assert_eq!(
+            segments,
+            [
+                &literal("files"),
+                &param("name"),
+                &Segment::CatchAll("rest".into())
+            ]
+        );
+        assert!(parts.iter().all(|part| !part.optional));
+    }
+
+    #[test]
+    fn parses_the_root_as_one_empty_literal() {
+        let parts = parse("/").unwrap();
+
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].segment, literal(""));
+    }
+
+    #[test]
+    fn parses_constraints() {
+        let parts = parse(r"/a/:id(\d+)/:n<u64>").unwrap();
+
+        let Segment::Param {
+            constraint: Some(Constraint::Regex(digits)),
+            ..
+        } = &parts[1].segment
+        else {
+            panic!("expected a regex constraint, got {:?}", parts[1]);
+        };
+        assert_eq!(digits.as_str(), r"^(?:\d+)$");
+
+        assert_eq!(
+            parts[2].segment,
+            Segment::Param {
+                name: "n".into(),
+                constraint: Some(Constraint::Type(ParamType::U64)),
+            }
+        );
+    }
+
+    #[test]
+    fn constraints_accept_whole_segments_only() {
+        let digits = Constraint::Regex(Regex::new(r"^(?:\d+)$").unwrap());
+        assert!(digits.accepts("42"));
+        assert!(!digits.accepts("42a"));
+        assert!(!digits.accepts("a42"));
+
+        let u8 = Constraint::Type(ParamType::U8);
+        assert!(u8.accepts("255"));
+        assert!(!u8.accepts("256"));
+        assert!(!u8.accepts("-1"));
+        assert!(!u8.accepts("+1"));
+
+        let i64 = Constraint::Type(ParamType::I64);
+        assert!(i64.accepts("-1"));
+        assert!(!i64.accepts("1.0"));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(parse("files").is_err());
+        assert!(parse("/a/:").is_err());
+        assert!(parse("/a/:1x").is_err());
+        assert!(parse("/a/*1x").is_err());
+        assert!(parse("/a/:x-").is_err());
+        assert!(parse("/a/:id(\\d+").is_err());
+        assert!(parse("/a/:id([)").is_err());
+        assert!(parse("/a/:id<f64>").is_err());
+        assert!(parse("/a/*").is_err());
+        assert!(parse("/a/*rest/b").is_err());
+        assert!(parse("/a/:x/:x").is_err());
+        assert!(parse("/a/:x/*x").is_err());
+    }
+
+    #[test]
+    fn limits_optional_segments() {
+        let pattern = |count: usize| (0..count).map(|i| format!("/:p{}?", i)).collect::<String>();
+
+        assert!(parse(&pattern(MAX_OPTIONAL_SEGMENTS)).is_ok());
+        assert!(parse(&pattern(MAX_OPTIONAL_SEGMENTS + 1)).is_err());
+    }
+
+    #[test]
+    fn expands_optional_segments() {
+        let variants = expand(&parse("/posts/:page?").unwrap());
+
+        assert_eq!(variants.len(), 2);
+        assert!(variants.contains(&vec![literal("posts")]));
+        assert!(variants.contains(&vec![literal("posts"), param("page")]));
+
+        let variants = expand(&parse("/:a?/:b?").unwrap());
+
+        assert_eq!(variants.len(), 4);
+        assert!(variants.contains(&vec![literal("")]));
+    }
+
+    #[test]
+    fn escapes_literals_in_regexes() {
+        let regex = regex("/v1.0/a+b");
+
+        assert!(regex.is_match("/v1.0/a+b"));
+        assert!(!regex.is_match("/v1x0/a+b"));
+        assert!(!regex.is_match("/v1.0/aab"));
+    }
+
+    #[test]
+    fn captures_params_within_a_segment() {
+        let regex = regex("/files/:name");
+        let captures = regex.captures("/files/a.txt").unwrap();
+
+        assert_eq!(&captures["name"], "a.txt");
+        assert!(!regex.is_match("/files/a/b"));
+        assert!(!regex.is_match("/files/"));
+    }
+
+    #[test]
+    fn captures_catch_alls_across_segments() {
+        let regex = regex("/static/*rest");
+        let captures = regex.captures("/static/css/site.css").unwrap();
+
+        assert_eq!(&captures["rest"], "css/site.css");
+        assert!(!regex.is_match("/static"));
+        assert!(!regex.is_match("/static/"));
+    }
+
+    #[test]
+    fn leaves_constraints_out_of_regexes() {
+        let regex = regex(r"/a/:id(\d+)");
+
+        assert_eq!(&regex.captures("/a/x").unwrap()["id"], "x");
+    }
+
+    #[test]
+    fn matches_optional_segments_in_regexes() {
+        let regex = regex("/posts/:page?");
+
+        assert!(regex.is_match("/posts"));
+        assert!(regex.is_match("/posts/2"));
+        assert!(!regex.is_match("/posts/"));
+
+        let root = self::regex("/:lang?");
+        assert!(root.is_match("/"));
+        assert!(root.is_match("/en"));
+    }
+
+    #[test]
+    fn compares_coverage() {
+        let segments = |pattern: &str| {
+            parse(pattern)
+                .unwrap()
+                .into_iter()
+                .map(|p| p.segment)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(covers(&segments("/a/:x"), &segments("/a/b")));
+        assert!(!covers(&segments("/a/b"), &segments("/a/:x")));
+        assert!(covers(&segments("/a/*rest"), &segments("/a/b/c")));
+        assert!(covers(&segments("/a/:x"), &segments(r"/a/:id(\d+)")));
+        assert!(!covers(&segments(r"/a/:id(\d+)"), &segments("/a/b")));
+        assert!(same_shape(&segments("/a/:x"), &segments("/a/:y")));
+        assert!(!same_shape(&segments("/a/:x"), &segments("/a/:x<u8>")));
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("a%20b"), "a b");
+        assert_eq!(percent_decode("..%2Fsecret"), "../secret");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("%C3%A9"), "é");
+    }
+}
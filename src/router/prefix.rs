@@ -0,0 +1,242 @@
+use std::sync::Arc;
+
+use crate::{request::HttpRequest, response::HttpResponse};
+
+use super::{
+    BoxFuture, ConfigureHandlers, HandlerContext, HttpError, HttpRouter, RouteError, RouteNames,
+};
+
+/// Dispatches to a different router depending on the start of the request
+/// path, so that routers built separately can be combined into one.
+///
+/// A router mounted under `/api/v1` gets `/api/v1/users` as `/users`, and
+/// `/api/v1` itself as `/`. The longest matching prefix wins; requests
+/// matching none go to the root router.
+pub struct PrefixRouter {
+    mounts: Vec<(String, Box<dyn HttpRouter>)>,
+    root: Box<dyn HttpRouter>,
+    /// Holds the named routes of the root router and of every mounted one.
+    context: HandlerContext,
+}
+
+impl PrefixRouter {
+    pub fn new(root: impl HttpRouter + 'static) -> Self {
+        PrefixRouter {
+            mounts: vec![],
+            context: HandlerContext::new(root.route_names()),
+            root: Box::new(root),
+        }
+    }
+
+    /// Mounts `router` under `prefix`. Wrap it in a `Pipeline` first to give
+    /// the whole group middleware of its own.
+    pub fn mount(
+        mut self: Self,
+        prefix: impl AsRef<str>,
+        router: impl HttpRouter + 'static,
+    ) -> Result<Self, RouteError> {
+        let prefix = prefix.as_ref();
+        let normalized = prefix.trim_end_matches('/');
+
+        if !normalized.starts_with('/') || normalized.contains(['?', '#']) {
+            return Err(RouteError::InvalidPattern {
+                reason: format!("Mount prefixes must be a path below '/', got {:?}", prefix),
+            });
+        }
+
+        if self.mounts.iter().any(|(mounted, _)| mounted == normalized) {
+            return Err(RouteError::MountConflict {
+                prefix: normalized.to_string(),
+            });
+        }
+
+        Arc::make_mut(&mut self.context.names).mount(normalized, &router.route_names())?;
+        self.mounts.push((normalized.to_string(), Box::new(router)));
+        self.mounts
+            .sort_by_key(|(prefix, _)| usize::MAX - prefix.len());
+
+        Ok(self)
+    }
+
+    /// The router a path belongs to, and the path relative to its mount
+    /// point.
+    fn route<'a>(self: &'a Self, path: &str) -> (&'a dyn HttpRouter, Option<String>) {
+        for (prefix, router) in &self.mounts {
+            let Some(rest) = path.strip_prefix(prefix.as_str()) else {
+                continue;
+            };
+
+            match rest {
+                "" => return (router.as_ref(), Some("/".into())),
+                rest if rest.starts_with('/') => return (router.as_ref(), Some(rest.into())),
+                _ => {}
+            }
+        }
+
+        (self.root.as_ref(), None)
+    }
+
+    fn prepare(self: &Self, mut req: HttpRequest, path: Option<String>) -> HttpRequest {
+        if let Some(path) = path {
+            req.target = req.target.with_path(path);
+        }
+
+        self.context.prepare(&mut req);
+
+        req
+    }
+}
+
+impl HttpRouter for PrefixRouter {
+    fn dispatch(self: &Self, req: HttpRequest) -> BoxFuture<'_, HttpResponse> {
+        let (router, path) = self.route(req.target.path());
+
+        router.dispatch(self.prepare(req, path))
+    }
+
+    fn precheck(self: &Self, req: &HttpRequest) -> Result<Option<usize>, HttpError> {
+        match self.route(req.target.path()) {
+            (router, None) => router.precheck(req),
+            (router, path) => router.precheck(&self.prepare(req.clone(), path)),
+        }
+    }
+
+    fn reject(self: &Self, req: HttpRequest, err: HttpError) -> BoxFuture<'_, HttpResponse> {
+        let (router, path) = self.route(req.target.path());
+
+        router.reject(self.prepare(req, path), err)
+    }
+
+    fn route_names(self: &Self) -> Arc<RouteNames> {
+        self.context.names.clone()
+    }
+}
+
+impl ConfigureHandlers for PrefixRouter {
+    fn handler_context(self: &mut Self) -> &mut HandlerContext {
+        &mut self.context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request::HttpMethod;
+
+    use super::super::{
+        extract::State,
+        testing::{dispatch, request},
+        ErrorFormat, HttpRegexEndpoint, RegexRouter,
+    };
+    use super::*;
+
+    /// A router answering every path with its name and the path it saw.
+    fn named(name: &'static str) -> RegexRouter {
+        let answer = move |req: HttpRequest| format!("{} {}", name, req.target.path());
+
+        RegexRouter::new(vec![
+            HttpRegexEndpoint::new(HttpMethod::GET, "/", answer),
+            HttpRegexEndpoint::new(HttpMethod::GET, "/*rest", answer),
+        ])
+        .unwrap()
+    }
+
+    async fn body(router: &PrefixRouter, path: &str) -> String {
+        dispatch(router, "GET", path).await.1
+    }
+
+    #[tokio::test]
+    async fn hands_mounted_routers_the_rest_of_the_path() {
+        let router = PrefixRouter::new(named("root"))
+            .mount("/api/v1", named("api"))
+            .unwrap();
+
+        assert_eq!(body(&router, "/api/v1").await, "api /");
+        assert_eq!(body(&router, "/api/v1/").await, "api /");
+        assert_eq!(body(&router, "/api/v1/x").await, "api /x");
+        assert_eq!(body(&router, "/api/v1/x/y").await, "api /x/y");
+        assert_eq!(body(&router, "/other").await, "root /other");
+    }
+
+    #[tokio::test]
+    async fn only_matches_whole_segments() {
+        let router = PrefixRouter::new(named("root"))
+            .mount("/api", named("api"))
+            .unwrap();
+
+        assert_eq!(body(&router, "/apix").await, "root /apix");
+        assert_eq!(body(&router, "/api-v2/x").await, "root /api-v2/x");
+    }
+
+    #[tokio::test]
+    async fn prefers_the_longest_prefix() {
+        let router = PrefixRouter::new(named("root"))
+            .mount("/api", named("api"))
+            .unwrap()
+            .mount("/api/v1/", named("v1"))
+            .unwrap();
+
+        assert_eq!(body(&router, "/api/v1/x").await, "v1 /x");
+        assert_eq!(body(&router, "/api/v2/x").await, "api /v2/x");
+    }
+
+    #[test]
+    fn refuses_mounting_twice_under_one_prefix() {
+        let router = PrefixRouter::new(named("root"))
+            .mount("/api", named("a"))
+            .unwrap();
+
+        assert!(matches!(
+            router.mount("/api/", named("b")),
+            Err(RouteError::MountConflict { prefix }) if prefix == "/api"
+        ));
+    }
+
+    #[test]
+    fn refuses_invalid_prefixes() {
+        for prefix in ["", "/", "api", "/api?x", "/api#x"] {
+            assert!(
+                matches!(
+                    PrefixRouter::new(named("root")).mount(prefix, named("api")),
+                    Err(RouteError::InvalidPattern { .. })
+                ),
+                "{:?}",
+                prefix
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn hands_state_down_unless_mounted_routers_have_their_own() {
+        let answer_state = || {
+            RegexRouter::new(vec![HttpRegexEndpoint::new(
+                HttpMethod::GET,
+                "/",
+                |State(name): State<&'static str>| *name,
+            )])
+            .unwrap()
+        };
+
+        let router = PrefixRouter::new(named("root"))
+            .mount("/own", answer_state().with_state("own"))
+            .unwrap()
+            .mount("/inherited", answer_state())
+            .unwrap()
+            .with_state("outer");
+
+        assert_eq!(body(&router, "/own").await, "own");
+        assert_eq!(body(&router, "/inherited").await, "outer");
+    }
+
+    #[tokio::test]
+    async fn renders_errors_of_mounted_routers() {
+        let router = PrefixRouter::new(named("root"))
+            .mount("/api", RegexRouter::new(vec![]).unwrap())
+            .unwrap()
+            .with_error_renderer(ErrorFormat::Json);
+
+        let res = router.dispatch(request("GET", "/api/missing")).await;
+
+        assert_eq!(res.status_code, 404);
+        assert_eq!(res.headers["content-type"], "application/problem+json");
+    }
+}
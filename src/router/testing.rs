@@ -0,0 +1,29 @@
+//! Helpers for the router tests.
+
+use crate::{
+    parser::{RequestLine, RequestTarget},
+    request::{HttpRequest, HttpRequestBuilder},
+};
+
+use super::HttpRouter;
+
+/// A request for `path`, as it would arrive without any headers or body.
+pub fn request(method: &str, path: &str) -> HttpRequest {
+    let request_line = RequestLine {
+        method,
+        target: RequestTarget::Origin { path, query: None },
+        version: (1, 1),
+    };
+
+    HttpRequestBuilder::from_request_line(request_line)
+        .unwrap()
+        .build()
+}
+
+/// Dispatches a request, returning the response's status and body.
+pub async fn dispatch(router: &impl HttpRouter, method: &str, path: &str) -> (usize, String) {
+    let res = router.dispatch(request(method, path)).await;
+    let body = res.body.unwrap_or_default();
+
+    (res.status_code, String::from_utf8_lossy(&body).into_owned())
+}
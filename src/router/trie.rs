@@ -0,0 +1,394 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    request::{HttpRequest, HttpRequestTarget},
+    response::HttpResponse,
+};
+
+use super::{
+    allow_header, call_endpoint, check_body_size, name_endpoints,
+    pattern::{self, Constraint, Segment},
+    render_error, resolve_route, validate_endpoints, BoxFuture, ConfigureHandlers, HandlerContext,
+    HttpError, HttpRegexEndpoint, HttpRequestParams, HttpRouter, Route, RouteError, RouteNames,
+};
+
+#[derive(Debug, Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    /// Constrained params come first, so they get the first try.
+    params: Vec<(Option<Constraint>, Node)>,
+    /// Routes whose pattern ends in a catch-all right below this node.
+    catch_all: Vec<usize>,
+    /// Routes whose pattern ends at this node.
+    routes: Vec<usize>,
+}
+
+impl Node {
+    fn insert(self: &mut Self, segments: &[Segment], route: usize) {
+        let Some((segment, rest)) = segments.split_first() else {
+            self.routes.push(route);
+            return;
+        };
+
+        match segment {
+            Segment::Literal(literal) => self
+                .statics
+                .entry(literal.clone())
+                .or_default()
+                .insert(rest, route),
+            Segment::Param { constraint, .. } => {
+                let i = match self.params.iter().position(|(c, _)| c == constraint) {
+                    Some(i) => i,
+                    None => {
+                        let i = match constraint {
+                            Some(_) => self.params.partition_point(|(c, _)| c.is_some()),
+                            None => self.params.len(),
+                        };
+
+                        self.params.insert(i, (constraint.clone(), Node::default()));
+                        i
+                    }
+                };
+
+                self.params[i].1.insert(rest, route)
+            }
+            Segment::CatchAll(_) => self.catch_all.push(route),
+        }
+    }
+
+    /// Walks every node matching the rest of a path, best match first,
+    /// handing `visit` the routes ending there along with what each param
+    /// and catch-all on the way matched. The walk stops once `visit` returns
+    /// true.
+    ///
+    /// Static segments are tried before params, and params before
+    /// catch-alls. Since a node can only be reached one way for a given
+    /// path, a walk that never stops early still visits each node at most
+    /// once, so the worst case is bounded by the size of the trie rather
+    /// than the path alone.
+    fn walk<'a>(
+        self: &'a Self,
+        segments: &[&str],
+        values: &mut Vec<String>,
+        visit: &mut dyn FnMut(&'a [usize], &[String]) -> bool,
+    ) -> bool {
+        let Some((segment, rest)) = segments.split_first() else {
+            return !self.routes.is_empty() && visit(&self.routes, values);
+        };
+
+        if let Some(child) = self.statics.get(*segment) {
+            if child.walk(rest, values, visit) {
+                return true;
+            }
+        }
+
+        if !segment.is_empty() {
+            for (constraint, child) in &self.params {
+                if let Some(constraint) = constraint {
+                    if !constraint.accepts(segment) {
+                        continue;
+                    }
+                }
+
+                values.push(segment.to_string());
+                let done = child.walk(rest, values, visit);
+                values.pop();
+
+                if done {
+                    return true;
+                }
+            }
+        }
+
+        if !self.catch_all.is_empty() && !segments.concat().is_empty() {
+            values.push(segments.join("/"));
+            let done = visit(&self.catch_all, values);
+            values.pop();
+
+            return done;
+        }
+
+        false
+    }
+}
+
+/// Routes requests through a trie of path segments, so finding an endpoint
+/// usually takes time proportional to the path rather than the number of
+/// routes. A request whose method the best match does not handle may walk
+/// more of the trie, though never any node twice.
+///
+/// Unlike `RegexRouter`, registration order does not matter: at each segment
+/// a literal beats a constrained `:param`, which beats an unconstrained one,
+/// which beats a `*catch_all`.
+#[derive(Debug)]
+pub struct TrieRouter {
+    root: Node,
+    endpoints: Vec<HttpRegexEndpoint>,
+    routes: Vec<TrieRoute>,
+    context: HandlerContext,
+}
+
+/// One concrete form of an endpoint's pattern, as inserted in the trie.
+#[derive(Debug)]
+struct TrieRoute {
+    endpoint: usize,
+    /// The names of the params and catch-all along the way, in path order.
+    param_names: Vec<String>,
+}
+
+impl TrieRouter {
+    pub fn new(endpoints: Vec<HttpRegexEndpoint>) -> Result<Self, RouteError> {
+        validate_endpoints(&endpoints, false)?;
+        let names = name_endpoints(&endpoints)?;
+
+        let mut root = Node::default();
+        let mut routes = Vec::with_capacity(endpoints.len());
+
+        for (i, endpoint) in endpoints.iter().enumerate() {
+            for segments in pattern::expand(endpoint.parts()) {
+                root.insert(&segments, routes.len());
+                routes.push(TrieRoute {
+                    endpoint: i,
+                    param_names: segments
+                        .into_iter()
+                        .filter_map(|segment| match segment {
+                            Segment::Literal(_) => None,
+                            Segment::Param { name, .. } | Segment::CatchAll(name) => Some(name),
+                        })
+                        .collect(),
+                });
+            }
+        }
+
+        Ok(TrieRouter {
+            root,
+            endpoints,
+            routes,
+            context: HandlerContext::new(Arc::new(names)),
+        })
+    }
+
+    fn route(self: &Self, req: &HttpRequest) -> (Route<'_, usize>, Vec<String>) {
+        if req.target == HttpRequestTarget::Asterisk {
+            let allow = allow_header(self.endpoints.iter().map(|endpoint| &endpoint.method));
+            return (Route::Options(allow), vec![]);
+        }
+
+        // `CONNECT host:port` names a server to tunnel to rather than a path.
+        if let HttpRequestTarget::Authority(_) = req.target {
+            return (Route::NotFound, vec![]);
+        }
+
+        let method_is_known = self
+            .endpoints
+            .iter()
+            .any(|endpoint| endpoint.method == req.method);
+
+        // The best match for the path may not handle the method while a
+        // worse one does, so keep looking until one does. Every match counts
+        // towards the `Allow` header.
+        let mut found = None;
+        let mut matching = Vec::new();
+
+        self.root.walk(
+            &pattern::split_path(req.target.path()),
+            &mut Vec::new(),
+            &mut |routes, values| {
+                let routes = routes
+                    .iter()
+                    .map(|i| (&self.endpoints[self.routes[*i].endpoint].method, i))
+                    .collect::<Vec<_>>();
+
+                if let Route::Endpoint(i) = resolve_route(&req.method, &routes, method_is_known) {
+                    found = Some((i, values.to_vec()));
+                    return true;
+                }
+
+                matching.extend(routes);
+                false
+            },
+        );
+
+        match found {
+            Some((i, values)) => (Route::Endpoint(i), values),
+            None => (
+                resolve_route(&req.method, &matching, method_is_known),
+                vec![],
+            ),
+        }
+    }
+}
+
+impl HttpRouter for TrieRouter {
+    fn dispatch(self: &Self, mut req: HttpRequest) -> BoxFuture<'_, HttpResponse> {
+        Box::pin(async move {
+            let (i, values) = match self.route(&req) {
+                (Route::Endpoint(i), values) => (*i, values),
+                (route, _) => match route.response().unwrap() {
+                    Ok(res) => return res,
+                    Err(err) => return self.reject(req, err).await,
+                },
+            };
+
+            self.context.prepare(&mut req);
+
+            let route = &self.routes[i];
+            let params = route
+                .param_names
+                .iter()
+                .cloned()
+                .zip(values.iter().map(|value| pattern::percent_decode(value)))
+                .collect::<HttpRequestParams>();
+
+            call_endpoint(&self.endpoints[route.endpoint], req, params).await
+        })
+    }
+
+    fn precheck(self: &Self, req: &HttpRequest) -> Result<Option<usize>, HttpError> {
+        match self.route(req) {
+            (Route::Endpoint(i), _) => {
+                check_body_size(&self.endpoints[self.routes[*i].endpoint], req)
+            }
+            // Automatic OPTIONS answers do not care about the body.
+            (route, _) => route.response().unwrap().map(|_| None),
+        }
+    }
+
+    fn reject(self: &Self, mut req: HttpRequest, err: HttpError) -> BoxFuture<'_, HttpResponse> {
+        self.context.prepare(&mut req);
+
+        Box::pin(async move { render_error(req.error_renderer.as_deref(), err) })
+    }
+
+    fn route_names(self: &Self) -> Arc<RouteNames> {
+        self.context.names.clone()
+    }
+}
+
+impl ConfigureHandlers for TrieRouter {
+    fn handler_context(self: &mut Self) -> &mut HandlerContext {
+        &mut self.context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request::HttpMethod;
+
+    use super::super::testing::{dispatch, request};
+    use super::*;
+
+    /// A router answering each pattern with its own name.
+    fn router(patterns: &[&'static str]) -> TrieRouter {
+        let endpoints = patterns
+            .iter()
+            .map(|&pattern| HttpRegexEndpoint::new(HttpMethod::GET, pattern, move || pattern))
+            .collect();
+
+        TrieRouter::new(endpoints).unwrap()
+    }
+
+    #[tokio::test]
+    async fn prefers_static_over_param_over_catch_all() {
+        let router = router(&["/files/*rest", "/files/:name", "/files/new"]);
+
+        assert_eq!(
+            dispatch(&router, "GET", "/files/new").await,
+            (200, "/files/new".into())
+        );
+        assert_eq!(
+            dispatch(&router, "GET", "/files/a").await,
+            (200, "/files/:name".into())
+        );
+        assert_eq!(
+            dispatch(&router, "GET", "/files/a/b").await,
+            (200, "/files/*rest".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn prefers_constrained_params() {
+        let router = router(&["/ids/:name", "/ids/:id<u64>"]);
+
+        assert_eq!(
+            dispatch(&router, "GET", "/ids/42").await,
+            (200, "/ids/:id<u64>".into())
+        );
+        assert_eq!(
+            dispatch(&router, "GET", "/ids/x").await,
+            (200, "/ids/:name".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn backtracks_when_a_static_branch_dead_ends() {
+        let router = router(&["/a/b/c", "/a/:x/d"]);
+
+        assert_eq!(
+            dispatch(&router, "GET", "/a/b/d").await,
+            (200, "/a/:x/d".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn params_match_a_single_segment() {
+        let router = router(&["/files/:name"]);
+
+        assert_eq!(dispatch(&router, "GET", "/files/a").await.0, 200);
+        assert_eq!(dispatch(&router, "GET", "/files/a/b").await.0, 404);
+        assert_eq!(dispatch(&router, "GET", "/files/").await.0, 404);
+        assert_eq!(dispatch(&router, "GET", "/files").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn catch_alls_need_at_least_one_segment() {
+        let router = router(&["/files/*rest"]);
+
+        assert_eq!(dispatch(&router, "GET", "/files/a/b/c").await.0, 200);
+        assert_eq!(dispatch(&router, "GET", "/files").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn matches_optional_segments() {
+        let router = router(&["/posts/:page?"]);
+
+        assert_eq!(dispatch(&router, "GET", "/posts").await.0, 200);
+        assert_eq!(dispatch(&router, "GET", "/posts/2").await.0, 200);
+        assert_eq!(dispatch(&router, "GET", "/posts/2/3").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn answers_other_methods_on_a_known_path_with_405() {
+        let router = router(&["/files/:name"]);
+        let res = router.dispatch(request("DELETE", "/files/a")).await;
+
+        assert_eq!(res.status_code, 405);
+        assert_eq!(res.headers.get("allow").unwrap(), "GET, HEAD, OPTIONS");
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_a_match_that_handles_the_method() {
+        let router = TrieRouter::new(vec![
+            HttpRegexEndpoint::new(HttpMethod::GET, "/files/new", || "new"),
+            HttpRegexEndpoint::new(HttpMethod::POST, "/files/:name", || "upload"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            dispatch(&router, "POST", "/files/new").await,
+            (200, "upload".into())
+        );
+        assert_eq!(
+            dispatch(&router, "GET", "/files/new").await,
+            (200, "new".into())
+        );
+        assert_eq!(dispatch(&router, "GET", "/files/a").await.0, 405);
+
+        let res = router.dispatch(request("DELETE", "/files/new")).await;
+        assert_eq!(res.status_code, 405);
+        assert_eq!(
+            res.headers.get("allow").unwrap(),
+            "GET, POST, HEAD, OPTIONS"
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use std::{collections::HashMap, fmt};
+
+use super::{
+    pattern::{self, Part, Segment},
+    HttpRequestParams, RouteError,
+};
+
+/// Why a URL could not be built for a named route.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UrlError {
+    UnknownRoute(String),
+    MissingParam {
+        route: String,
+        param: String,
+    },
+    /// The value does not satisfy the param's constraint, so the URL would
+    /// not lead back to the route.
+    InvalidParam {
+        route: String,
+        param: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlError::UnknownRoute(name) => write!(f, "No route is named {:?}", name),
+            UrlError::MissingParam { route, param } => {
+                write!(f, "Route {:?} needs a value for :{}", route, param)
+            }
+            UrlError::InvalidParam {
+                route,
+                param,
+                value,
+            } => write!(
+                f,
+                "{:?} is not a valid value for :{} in route {:?}",
+                value, param, route
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UrlError {}
+
+/// Percent-encodes everything but unreserved characters (RFC 3986, section
+/// 2.3), so that any value fits in a single path segment.
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+/// The patterns of a router's named endpoints, by name, for building URLs
+/// that lead back to them.
+#[derive(Clone, Debug, Default)]
+pub struct RouteNames {
+    routes: HashMap<String, Vec<Part>>,
+}
+
+impl RouteNames {
+    /// Adds a route. Endpoints for different methods on the same path may
+    /// share a name, but a name cannot stand for two different paths.
+    pub fn insert(self: &mut Self, name: &str, parts: Vec<Part>) -> Result<(), RouteError> {
+        if self
+            .routes
+            .get(name)
+            .is_some_and(|existing| *existing != parts)
+        {
+            return Err(RouteError::DuplicateName {
+                name: name.to_string(),
+            });
+        }
+
+        self.routes.insert(name.to_string(), parts);
+
+        Ok(())
+    }
+
+    /// Adds the routes of a router mounted under `prefix`.
+    pub fn mount(self: &mut Self, prefix: &str, names: &RouteNames) -> Result<(), RouteError> {
+        let prefix = pattern::split_path(prefix)
+            .into_iter()
+            .map(|literal| Part {
+                segment: Segment::Literal(literal.to_string()),
+                optional: false,
+            })
+            .collect::<Vec<_>>();
+
+        let root = Part {
+            segment: Segment::Literal("".into()),
+            optional: false,
+        };
+
+        for (name, parts) in &names.routes {
+            let mut prefixed = prefix.clone();
+
+            // The mounted router's own root is the prefix itself.
+            if !(parts.len() == 1 && parts[0] == root) {
+                prefixed.extend(parts.iter().cloned());
+            }
+
+            self.insert(name, prefixed)?;
+        }
+
+        Ok(())
+    }
+
+    /// The path of the route called `name`, with its params filled in from
+    /// `params` and percent-encoded. Optional params may be left out.
+    pub fn url_for(
+        self: &Self,
+        name: &str,
+        params: &HttpRequestParams,
+    ) -> Result<String, UrlError> {
+        let parts = self
+            .routes
+            .get(name)
+            .ok_or_else(|| UrlError::UnknownRoute(name.to_string()))?;
+
+        let mut url = String::new();
+
+        for part in parts {
+            let (param, constraint) = match &part.segment {
+                Segment::Literal(literal) => {
+                    url.push('/');
+                    url.push_str(literal);
+                    continue;
+                }
+                Segment::Param { name, constraint } => (name, constraint.as_ref()),
+                Segment::CatchAll(name) => (name, None),
+            };
+
+            let value = match params.get(param) {
+                Some(value) => value,
+                None if part.optional => continue,
+                None => {
+                    return Err(UrlError::MissingParam {
+                        route: name.to_string(),
+                        param: param.clone(),
+                    })
+                }
+            };
+
+            let encoded = match &part.segment {
+                Segment::CatchAll(_) => value
+                    .split('/')
+                    .map(percent_encode)
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                _ => percent_encode(value),
+            };
+
+            let accepted = match constraint {
+                Some(constraint) => constraint.accepts(&encoded),
+                None => true,
+            };
+
+            if encoded.is_empty() || !accepted {
+                return Err(UrlError::InvalidParam {
+                    route: name.to_string(),
+                    param: param.clone(),
+                    value: value.clone(),
+                });
+            }
+
+            url.push('/');
+            url.push_str(&encoded);
+        }
+
+        if url.is_empty() {
+            url.push('/');
+        }
+
+        Ok(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request::HttpMethod;
+
+    use super::super::{HttpRegexEndpoint, HttpRouter, PrefixRouter, RegexRouter};
+    use super::*;
+
+    fn names(routes: &[(&str, &str)]) -> RouteNames {
+        let mut names = RouteNames::default();
+        for (name, pattern) in routes {
+            names
+                .insert(name, pattern::parse(pattern).unwrap())
+                .unwrap();
+        }
+
+        names
+    }
+
+    fn params(entries: &[(&str, &str)]) -> HttpRequestParams {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn percent_encodes_params() {
+        let names = names(&[("file", "/files/:name")]);
+        let url = |value| names.url_for("file", &params(&[("name", value)]));
+
+        assert_eq!(url("a-b_c.d~e"), Ok("/files/a-b_c.d~e".into()));
+        assert_eq!(url("a b/c"), Ok("/files/a%20b%2Fc".into()));
+        assert_eq!(
+            url("?#[]@!$&'()*+,;=%"),
+            Ok("/files/%3F%23%5B%5D%40%21%24%26%27%28%29%2A%2B%2C%3B%3D%25".into())
+        );
+        assert_eq!(url("é"), Ok("/files/%C3%A9".into()));
+    }
+
+    #[test]
+    fn requires_params_unless_optional() {
+        let names = names(&[("post", "/posts/:id/comments/:page?")]);
+
+        assert_eq!(
+            names.url_for("post", &params(&[("id", "1")])),
+            Ok("/posts/1/comments".into())
+        );
+        assert_eq!(
+            names.url_for("post", &params(&[("id", "1"), ("page", "2")])),
+            Ok("/posts/1/comments/2".into())
+        );
+        assert_eq!(
+            names.url_for("post", &params(&[("page", "2")])),
+            Err(UrlError::MissingParam {
+                route: "post".into(),
+                param: "id".into()
+            })
+        );
+        assert_eq!(
+            names.url_for("posts", &params(&[])),
+            Err(UrlError::UnknownRoute("posts".into()))
+        );
+    }
+
+    #[test]
+    fn keeps_slashes_in_catch_alls() {
+        let names = names(&[("static", "/static/*path")]);
+        let url = |value| names.url_for("static", &params(&[("path", value)]));
+
+        assert_eq!(
+            url("css/site main.css"),
+            Ok("/static/css/site%20main.css".into())
+        );
+        assert_eq!(
+            url(""),
+            Err(UrlError::InvalidParam {
+                route: "static".into(),
+                param: "path".into(),
+                value: "".into()
+            })
+        );
+    }
+
+    #[test]
+    fn checks_values_against_constraints() {
+        let names = names(&[("user", "/users/:id<u64>"), ("tag", "/tags/:tag([a-z]+)")]);
+
+        assert_eq!(
+            names.url_for("user", &params(&[("id", "42")])),
+            Ok("/users/42".into())
+        );
+        assert_eq!(
+            names.url_for("user", &params(&[("id", "-1")])),
+            Err(UrlError::InvalidParam {
+                route: "user".into(),
+                param: "id".into(),
+                value: "-1".into()
+            })
+        );
+        assert!(names.url_for("tag", &params(&[("tag", "rust")])).is_ok());
+        assert!(names.url_for("tag", &params(&[("tag", "Rust")])).is_err());
+    }
+
+    #[test]
+    fn prefixes_names_from_mounted_routers() {
+        let api = RegexRouter::new(vec![
+            HttpRegexEndpoint::new(HttpMethod::GET, "/", || "").name("api"),
+            HttpRegexEndpoint::new(HttpMethod::GET, "/users/:id", || "").name("user"),
+        ])
+        .unwrap();
+        let root = RegexRouter::new(vec![
+            HttpRegexEndpoint::new(HttpMethod::GET, "/", || "").name("home")
+        ])
+        .unwrap();
+
+        let router = PrefixRouter::new(root).mount("/api/v1/", api).unwrap();
+        let names = router.route_names();
+
+        assert_eq!(names.url_for("home", &params(&[])), Ok("/".into()));
+        assert_eq!(names.url_for("api", &params(&[])), Ok("/api/v1".into()));
+        assert_eq!(
+            names.url_for("user", &params(&[("id", "a/b")])),
+            Ok("/api/v1/users/a%2Fb".into())
+        );
+    }
+
+    #[test]
+    fn refuses_a_mounted_name_standing_for_another_path() {
+        let mut root = names(&[("user", "/users/:id")]);
+
+        assert_eq!(
+            root.mount("/api", &names(&[("user", "/users/:id")])),
+            Err(RouteError::DuplicateName {
+                name: "user".into()
+            })
+        );
+    }
+}
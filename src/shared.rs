@@ -1,12 +1,12 @@
 use std::collections::HashMap;
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum HttpEncodingScheme {
     Gzip,
     None,
 }
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct HttpHeaders(HashMap<String, String>);
 
 impl HttpHeaders {
@@ -16,16 +16,14 @@ impl HttpHeaders {
         self.0.insert(key, value.as_ref().to_owned());
     }
 
-    pub fn get(&mut self, key: impl AsRef<str>) -> Option<&String> {
+    pub fn get(&self, key: impl AsRef<str>) -> Option<&String> {
         let key = key.as_ref().to_owned().to_lowercase();
 
         self.0.get(&key)
     }
 
-    pub fn remove(&mut self, key: impl AsRef<str>) -> Option<String> {
-        let key = key.as_ref().to_owned().to_lowercase();
-
-        self.0.remove(&key)
+    /// Every header, by lowercase name.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.iter()
     }
 }
-
@@ -0,0 +1,53 @@
+use tokio::{signal, sync::watch};
+
+/// Lets connections find out that the server has started shutting down.
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+/// Starts a graceful shutdown for every `ShutdownSignal` it was created with.
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+pub fn channel() -> (ShutdownTrigger, ShutdownSignal) {
+    let (sender, receiver) = watch::channel(false);
+
+    (ShutdownTrigger(sender), ShutdownSignal(receiver))
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(self: &Self) {
+        let _ = self.0.send(true);
+    }
+}
+
+impl ShutdownSignal {
+    pub fn is_triggered(self: &Self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once shutdown has started, immediately if it already has.
+    pub async fn triggered(self: &mut Self) {
+        // An error means the trigger was dropped, which only happens as the
+        // server goes away anyway.
+        let _ = self.0.wait_for(|triggered| *triggered).await;
+    }
+}
+
+/// Resolves when the process receives SIGINT or, on Unix, SIGTERM.
+pub async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler");
+
+        tokio::select! {
+            _ = signal::ctrl_c() => info!("Received SIGINT"),
+            _ = sigterm.recv() => info!("Received SIGTERM"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = signal::ctrl_c().await;
+        info!("Received SIGINT");
+    }
+}
@@ -0,0 +1,41 @@
+use std::{
+    any::{type_name, Any},
+    fmt::{self, Debug},
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Everything the server's own handlers need besides the request itself.
+#[derive(Debug)]
+pub struct AppState {
+    /// Where files are served from and uploaded to.
+    pub data_dir: PathBuf,
+}
+
+/// State shared by every request a router dispatches, of whatever type the
+/// application chooses. Handlers get it back out with `HttpRequest::state`.
+#[derive(Clone)]
+pub struct SharedState {
+    state: Arc<dyn Any + Send + Sync>,
+    type_name: &'static str,
+}
+
+impl SharedState {
+    pub fn new<T: Any + Send + Sync>(state: T) -> Self {
+        SharedState {
+            state: Arc::new(state),
+            type_name: type_name::<T>(),
+        }
+    }
+
+    /// The state, if it is of type `T`.
+    pub fn get<T: Any + Send + Sync>(self: &Self) -> Option<Arc<T>> {
+        self.state.clone().downcast().ok()
+    }
+}
+
+impl Debug for SharedState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SharedState<{}>", self.type_name)
+    }
+}